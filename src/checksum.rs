@@ -0,0 +1,71 @@
+use crate::protos::sffs::ChecksumAlgorithm;
+use sha2::{Digest, Sha256};
+
+use std::fs::File;
+use std::io::{self, prelude::*, SeekFrom};
+
+pub enum Hasher {
+    Sha256(Sha256),
+    Crc32(crc32fast::Hasher),
+}
+
+impl Hasher {
+    pub fn new(algorithm: ChecksumAlgorithm) -> Self {
+        match algorithm {
+            ChecksumAlgorithm::SHA256 => Hasher::Sha256(Sha256::new()),
+            ChecksumAlgorithm::CRC32 => Hasher::Crc32(crc32fast::Hasher::new()),
+        }
+    }
+
+    #[inline]
+    pub fn update(&mut self, data: &[u8]) {
+        match self {
+            Hasher::Sha256(h) => h.input(data),
+            Hasher::Crc32(h) => h.update(data),
+        }
+    }
+
+    // lowercase hex digest
+    pub fn finish(self) -> String {
+        match self {
+            Hasher::Sha256(h) => format!("{:x}", h.result()),
+            Hasher::Crc32(h) => format!("{:08x}", h.finalize()),
+        }
+    }
+}
+
+pub fn parse_algorithm(name: &str) -> Option<ChecksumAlgorithm> {
+    match name.to_ascii_lowercase().as_str() {
+        "sha256" => Some(ChecksumAlgorithm::SHA256),
+        "crc32" => Some(ChecksumAlgorithm::CRC32),
+        _ => None,
+    }
+}
+
+// Digest `count` bytes from `start` (or the whole file when range is None).
+// Returns the digest and the number of bytes actually read.
+pub fn digest_file(
+    file: &mut File,
+    algorithm: ChecksumAlgorithm,
+    range: Option<(u64, u64)>,
+) -> io::Result<(String, u64)> {
+    let (start, count) = range.unwrap_or((0, std::u64::MAX));
+    file.seek(SeekFrom::Start(start))?;
+    let mut reader = file.take(count);
+
+    let mut hasher = Hasher::new(algorithm);
+    let mut buf = vec![0u8; 64 * 1024];
+    let mut bytes = 0u64;
+    loop {
+        let len = match reader.read(&mut buf) {
+            Ok(0) => break,
+            Ok(len) => len,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        hasher.update(&buf[..len]);
+        bytes += len as u64;
+    }
+
+    Ok((hasher.finish(), bytes))
+}
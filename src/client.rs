@@ -2,240 +2,1303 @@
 // extern crate log;
 // #![feature(let_chains)]
 use chrono::prelude::*;
-use grpcio::{ChannelBuilder, EnvBuilder};
+use glob::Pattern;
+use grpcio::{RpcStatus, RpcStatusCode};
 use nix::unistd;
 
+use sffs::checksum;
 use sffs::filter::MetaDataFilter;
-use sffs::protos::{sffs as ffs, sffs_grpc::SffsClient, MAX_BLOCK_SIZE};
+use sffs::protos::{sffs as ffs, MAX_BLOCK_SIZE};
+use sffs::sffsclient::RemoteFile;
 use sffs::CommonErrorKind::{InvalidArgument, NotFound};
-use sffs::common;
+use sffs::walk::Walk;
+use sffs::SFFSClient;
 
-use std::fs::File;
+use rustyline::completion::{Completer, Pair};
+use rustyline::error::ReadlineError;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::validate::Validator;
+use rustyline::{Context, Editor, Helper};
+
+use std::convert::TryFrom;
+use std::env;
+use std::ffi::OsString;
+use std::fs::{self, File};
 use std::io::prelude::*;
 use std::io::{self, BufReader};
-use std::sync::Arc;
+use std::iter::Peekable;
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+const COMMANDS: &[&str] = &[
+    "cat", "cd", "df", "du", "filecount", "find", "get", "getdir", "getxattr", "head", "lcd", "lls", "ln", "lpwd",
+    "ls", "lsxattr", "mget", "mkdir", "mput", "put", "pwd", "randomread", "rmxattr", "setxattr", "stat", "sum",
+    "tail",
+];
+
+// Whether the path given as argument `index` of `cmd`, not counting flags,
+// is on this side. Transfers take their source first and their target second.
+fn is_local_arg(cmd: &str, index: usize) -> bool {
+    match cmd {
+        "lcd" | "lls" => true,
+        "put" | "mput" => index == 0,
+        "get" | "mget" => index == 1,
+        _ => false,
+    }
+}
+
+// Completes command names, and paths after them using the remote listing,
+// or the local one where is_local_arg says so.
+struct ReplHelper<'a> {
+    client: &'a SFFSClient,
+}
+
+impl<'a> ReplHelper<'a> {
+    fn complete_path(&self, word: &str, local: bool) -> sffs::Result<Vec<Pair>> {
+        let (dir, prefix) = match word.rfind('/') {
+            Some(i) => (&word[..=i], &word[i + 1..]),
+            None => ("", word),
+        };
+        let listdir = if dir.is_empty() { "." } else { dir };
+
+        // (name, isdir) of each entry
+        let mut names = Vec::new();
+        if local {
+            for entry in fs::read_dir(listdir)? {
+                let entry = entry?;
+                let isdir = entry.file_type().map(|t| t.is_dir()).unwrap_or(false);
+                names.push((entry.file_name().to_string_lossy().into_owned(), isdir));
+            }
+            names.sort();
+        } else {
+            let mut sort = ffs::SortOption::new();
+            sort.set_key(ffs::SortKey::NAME);
+            for entry in self.client.list(listdir, None, sort)? {
+                let mut entry = entry?;
+                names.push((entry.take_name(), entry.get_isdir()));
+            }
+        }
 
-fn prompt() {
-    print!("$ ");
-    io::stdout().flush().expect("Cannot flush stdout.");
+        let mut candidates = Vec::new();
+        for (name, isdir) in names {
+            if name == "." || name == ".." || !name.starts_with(prefix) {
+                continue;
+            }
+            let suffix = if isdir { "/" } else { "" };
+            candidates.push(Pair {
+                display: format!("{}{}", name, suffix),
+                replacement: format!("{}{}{}", escape_arg(dir), escape_arg(&name), suffix),
+            });
+        }
+        Ok(candidates)
+    }
 }
 
-struct RemoteFile<'a> {
-    client: Option<&'a SffsClient>,
-    isdir: bool,
+impl<'a> Completer for ReplHelper<'a> {
+    type Candidate = Pair;
+
+    fn complete(&self, line: &str, pos: usize, _ctx: &Context<'_>) -> rustyline::Result<(usize, Vec<Pair>)> {
+        let (start, word) = match last_word(&line[..pos]) {
+            Some(last) => last,
+            None => return Ok((pos, Vec::new())), // inside a comment
+        };
+
+        if line[..start].trim().is_empty() {
+            let candidates = (COMMANDS.iter())
+                .filter(|cmd| cmd.starts_with(&word))
+                .map(|cmd| Pair {
+                    display: cmd.to_string(),
+                    replacement: format!("{} ", cmd),
+                })
+                .collect();
+            return Ok((start, candidates));
+        }
+        // the words before end in whitespace, so no quote is left open
+        let before = split_args(&line[..start]).unwrap_or_default();
+        let cmd = before.first().map_or("", String::as_str);
+        let index = before.iter().skip(1).filter(|arg| !arg.starts_with('-')).count();
+        // a failed listing just completes nothing
+        let candidates = self.complete_path(&word, is_local_arg(cmd, index)).unwrap_or_default();
+        Ok((start, candidates))
+    }
 }
 
-impl<'a> RemoteFile<'a> {
-    fn open(client: &'a SffsClient, name: &str) -> sffs::Result<Self> {
-        let is_found = client.openfiletoread(&name.into())?;
-        Self::_new(client, name, false, is_found.get_value())
+impl<'a> Hinter for ReplHelper<'a> {}
+impl<'a> Highlighter for ReplHelper<'a> {}
+impl<'a> Validator for ReplHelper<'a> {}
+impl<'a> Helper for ReplHelper<'a> {}
+
+// quotes a completed name so that split_args gives it back unchanged
+fn escape_arg(arg: &str) -> String {
+    let mut escaped = String::with_capacity(arg.len());
+    for c in arg.chars() {
+        if c.is_whitespace() || "\\'\"#".contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
     }
+    escaped
+}
 
-    fn openlist(client: &'a SffsClient, name: &str, option: Option<String>) -> sffs::Result<Self> {
-        let mut request = ffs::ListRequest::new();
-        request.set_dir(name.to_owned());
-        if let Some(option) = option {
-            request.set_option(option.into());
+// Start of the word being typed at the end of `line` and its text without
+// quotes and escapes, by the rules of split_args. None inside a comment.
+fn last_word(line: &str) -> Option<(usize, String)> {
+    let mut start = 0;
+    let mut word: Option<String> = None;
+    let mut chars = line.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {
+                start = i + c.len_utf8();
+                word = None;
+            }
+            '#' if word.is_none() => return None,
+            '\'' => {
+                let word = word.get_or_insert_with(String::new);
+                for (_, c) in chars.by_ref() {
+                    if c == '\'' {
+                        break;
+                    }
+                    word.push(c);
+                }
+            }
+            '"' => {
+                let word = word.get_or_insert_with(String::new);
+                while let Some((_, c)) = chars.next() {
+                    match c {
+                        '"' => break,
+                        '\\' => match chars.next() {
+                            Some((_, c @ '"')) | Some((_, c @ '\\')) => word.push(c),
+                            Some((_, c)) => {
+                                word.push('\\');
+                                word.push(c);
+                            }
+                            None => {}
+                        },
+                        c => word.push(c),
+                    }
+                }
+            }
+            '\\' => {
+                let word = word.get_or_insert_with(String::new);
+                word.extend(chars.next().map(|(_, c)| c));
+            }
+            c => word.get_or_insert_with(String::new).push(c),
         }
+    }
+    Some((start, word.unwrap_or_default()))
+}
 
-        let is_found = client.openlist(&request)?;
-        Self::_new(client, name, true, is_found.get_value())
+// Where command lines come from, a script for -f and the line editor otherwise.
+enum Input<'a> {
+    Script(Box<dyn BufRead>),
+    Repl {
+        editor: Editor<ReplHelper<'a>>,
+        host: String,
+        history: Option<PathBuf>,
+        cwd: String,
+    },
+}
+
+impl<'a> Input<'a> {
+    fn repl(client: &'a SFFSClient, host: &str) -> Self {
+        let mut editor = Editor::new();
+        editor.set_helper(Some(ReplHelper { client }));
+
+        let history = env::var_os("HOME").map(|home| PathBuf::from(home).join(".sffs_history"));
+        if let Some(ref history) = history {
+            let _ = editor.load_history(history); // absent on first use
+        }
+        Input::Repl {
+            editor,
+            host: host.to_owned(),
+            history,
+            cwd: remote_cwd(client),
+        }
     }
 
-    fn create(client: &'a SffsClient, name: &str) -> sffs::Result<Self> {
-        let is_found = client.openfiletowrite(&name.into())?;
-        Self::_new(client, name, false, is_found.get_value())
+    // the prompt shows the remote directory, which only a cd changes
+    fn refresh_cwd(&mut self, client: &SFFSClient) {
+        if let Input::Repl { cwd, .. } = self {
+            *cwd = remote_cwd(client);
+        }
     }
 
-    #[inline]
-    fn _new(client: &'a SffsClient, name: &str, isdir: bool, is_found: bool) -> sffs::Result<Self> {
-        if is_found {
-            Ok(Self {
-                client: Some(client),
-                isdir,
-            })
-        } else {
-            Err(NotFound(name.to_owned()).into())
+    // None at the end of input
+    fn next_line(&mut self) -> Option<String> {
+        match self {
+            Input::Script(inputstream) => {
+                let mut cmdline = String::new();
+                inputstream.read_line(&mut cmdline).expect("Could not read a line!");
+
+                // EOF (with no '\n')
+                if cmdline.is_empty() {
+                    None
+                } else {
+                    Some(cmdline)
+                }
+            }
+            Input::Repl {
+                editor,
+                host,
+                history,
+                cwd,
+            } => loop {
+                match editor.readline(&format!("{}:{}$ ", host, cwd)) {
+                    Ok(cmdline) => {
+                        if !cmdline.trim().is_empty() && editor.add_history_entry(cmdline.as_str()) {
+                            if let Some(ref history) = *history {
+                                if let Err(e) = editor.save_history(history) {
+                                    eprintln!("Cannot save history: {}", e);
+                                }
+                            }
+                        }
+                        return Some(cmdline);
+                    }
+                    // ^C drops the line being typed, like a shell
+                    Err(ReadlineError::Interrupted) => continue,
+                    Err(ReadlineError::Eof) => return None,
+                    Err(e) => {
+                        eprintln!("Could not read a line: {}", e);
+                        return None;
+                    }
+                }
+            },
+        }
+    }
+}
+
+// "?" for the prompt when the server cannot tell, saying why
+fn remote_cwd(client: &SFFSClient) -> String {
+    client.getdir().unwrap_or_else(|e| {
+        eprintln!("Cannot get the remote directory: {}", e);
+        "?".to_owned()
+    })
+}
+
+// Splits a command line the way a POSIX shell would: single quotes keep
+// everything, double quotes only honour \" and \\, a backslash escapes the
+// next character and a # starting a word comments out the rest of the line.
+fn split_args(line: &str) -> Result<Vec<String>, &'static str> {
+    let mut args = Vec::new();
+    let mut arg: Option<String> = None; // Some even if empty once quotes are seen
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => args.extend(arg.take()),
+            '#' if arg.is_none() => break,
+            '\'' => {
+                let arg = arg.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => arg.push(c),
+                        None => return Err("unterminated single quote"),
+                    }
+                }
+            }
+            '"' => {
+                let arg = arg.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(c @ '"') | Some(c @ '\\') => arg.push(c),
+                            Some(c) => {
+                                arg.push('\\');
+                                arg.push(c);
+                            }
+                            None => return Err("unterminated double quote"),
+                        },
+                        Some(c) => arg.push(c),
+                        None => return Err("unterminated double quote"),
+                    }
+                }
+            }
+            '\\' => match chars.next() {
+                Some('\n') | None => return Err("trailing backslash"),
+                Some(c) => arg.get_or_insert_with(String::new).push(c),
+            },
+            c => arg.get_or_insert_with(String::new).push(c),
+        }
+    }
+    args.extend(arg);
+    Ok(args)
+}
+
+// Accepts `[option] [path]` or `[path] [option]`, path defaults to ".".
+// The option is parsed here, so a malformed one never reaches the server.
+fn path_and_filter<'a>(
+    cmd_iter: &mut impl Iterator<Item = &'a str>,
+) -> sffs::Result<(&'a str, Option<MetaDataFilter>)> {
+    let (path, option) = match cmd_iter.next() {
+        Some(token) => {
+            if MetaDataFilter::is_pattern_like(token) {
+                (cmd_iter.next().unwrap_or("."), Some(token)) // token as option
+            } else {
+                (token, cmd_iter.next()) // token as path
+            }
+        }
+        None => (".", None), // there won't be another token afterwards
+    };
+    let filter = match option {
+        Some(option) => Some(MetaDataFilter::new(option)?),
+        None => None,
+    };
+    Ok((path, filter))
+}
+
+// as path_and_filter, the option being sent as a typed filter
+fn path_and_option<'a>(
+    cmd_iter: &mut impl Iterator<Item = &'a str>,
+) -> sffs::Result<(&'a str, Option<ffs::ListOption>)> {
+    let (path, filter) = path_and_filter(cmd_iter)?;
+    Ok((path, filter.map(|filter| (&filter).into())))
+}
+
+fn print_entry(entry: &ffs::DirEntry, longopt: bool) {
+    print!("{}", entry.get_name());
+    if entry.get_isdir() { print!("/"); }
+    if longopt {
+        let time = Utc
+            .timestamp(entry.get_modifytime(), 0)
+            .format("%a\t%b\t%d\t%T\t%Z\t%Y");
+        print!("\t{}\t{}\t{}", entry.get_nlink(), entry.get_size(), time);
+    }
+    print!("\n");
+}
+
+fn human_size(bytes: i64) -> String {
+    const UNITS: [&str; 5] = ["K", "M", "G", "T", "P"];
+    if bytes < 1024 {
+        return bytes.to_string();
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit + 1 < UNITS.len() {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1}{}", size, UNITS[unit])
+}
+
+#[inline]
+fn percentage(used: i64, total: i64) -> i64 {
+    if total <= 0 {
+        0
+    } else {
+        (used * 100 + total - 1) / total // round up like df(1)
+    }
+}
+
+fn pull_xattrs(client: &SFFSClient, remotepath: &str, localpath: &Path) -> sffs::Result<usize> {
+    let mut count = 0;
+    for name in client.listxattr(remotepath)? {
+        if let Some(value) = client.getxattr(remotepath, &name)? {
+            // e.g. security.* and trusted.* usually need privileges here
+            match xattr::set(localpath, &name, &value) {
+                Ok(()) => count += 1,
+                Err(e) => eprintln!("cannot set local xattr {}: {}", name, e),
+            }
         }
     }
+    Ok(count)
+}
+
+// Names and paths travel as strings, those which are not UTF8 cannot be
+// carried by the protocol.
+fn protocol_string(name: impl Into<OsString>) -> Result<String, &'static str> {
+    name.into().into_string().map_err(|_| "name is not UTF8")
+}
 
-    fn close(&mut self) -> sffs::Result<()> {
-        let client = match self.client.take() {
-            Some(client) => client,
-            None => return Ok(()),
+fn push_xattrs(client: &SFFSClient, localpath: &Path, remotepath: &str) -> sffs::Result<usize> {
+    let mut count = 0;
+    for name in xattr::list(localpath)? {
+        let name = match protocol_string(name) {
+            Ok(name) => name,
+            Err(_) => continue,
         };
-        let reply = if self.isdir {
-            client.closelist(&ffs::Void::new())?
+        let value = match xattr::get(localpath, &name)? {
+            Some(value) => value,
+            None => continue,
+        };
+        if client.setxattr(remotepath, &name, value)? {
+            count += 1;
         } else {
-            client.closefile(&ffs::Void::new())?
+            eprintln!("cannot set remote xattr {}", name);
+        }
+    }
+    Ok(count)
+}
+
+// Status line redrawn on stderr while one file is transferred.
+struct Progress<'a> {
+    name: &'a str,
+    total: Option<u64>,
+    start: Instant,
+    drawn: Option<Instant>,
+}
+
+impl<'a> Progress<'a> {
+    const INTERVAL: Duration = Duration::from_millis(200);
+
+    fn new(name: &'a str, total: Option<u64>) -> Self {
+        Self {
+            name,
+            total,
+            start: Instant::now(),
+            drawn: None,
+        }
+    }
+
+    fn update(&mut self, bytes: u64) {
+        let now = Instant::now();
+        match self.drawn {
+            Some(drawn) if now.duration_since(drawn) < Self::INTERVAL => return,
+            _ => self.drawn = Some(now),
+        }
+
+        let rate = bytes as f64 / now.duration_since(self.start).as_secs_f64().max(0.001);
+        let mut line = format!("{} {}", self.name, human_size(bytes as i64));
+        if let Some(total) = self.total.filter(|&total| total > 0) {
+            line += &format!(" {}%", bytes.min(total) * 100 / total);
+        }
+        line += &format!(" {}/s", human_size(rate as i64));
+        if let Some(total) = self.total {
+            let eta = total.saturating_sub(bytes) as f64 / rate.max(1.0);
+            line += &format!(" ETA {}:{:02}", eta as u64 / 60, eta as u64 % 60);
+        }
+        // \x1b[K clears what is left of a longer previous line
+        eprint!("\r{}\x1b[K", line);
+        let _ = io::stderr().flush();
+    }
+
+    fn finish(&self) {
+        if self.drawn.is_some() {
+            eprint!("\r\x1b[K");
+        }
+    }
+}
+
+// Flags and running totals of a get or put, over one file or many.
+struct Transfer {
+    withxattr: bool,
+    progress: bool,
+    start: Instant,
+    files: usize,
+    bytes: u64,
+    xattrs: usize,
+    failed: usize,
+}
+
+impl Transfer {
+    fn new(withxattr: bool, progress: bool) -> Self {
+        Self {
+            withxattr,
+            progress,
+            start: Instant::now(),
+            files: 0,
+            bytes: 0,
+            xattrs: 0,
+            failed: 0,
+        }
+    }
+
+    fn get_file(&mut self, client: &SFFSClient, remotepath: &str, localpath: &Path) -> sffs::Result<u64> {
+        // open the remote file before truncating the local one, so a missing
+        // or unreadable file leaves it alone
+        let remotefile = client.open(remotepath)?;
+        // only for the progress, servers without stat leave it unknown
+        let size = client.stat(remotepath).ok().map(|entry| entry.get_size() as u64);
+
+        // open local file
+        let _ = unistd::unlink(localpath);
+        let mut localfile = File::create(localpath).map_err(|_| "cannot open local file as write")?;
+
+        // read remote data to local file
+        let mut progress = Progress::new(remotepath, size);
+        let show = self.progress;
+        let bytes = client.get_opened(remotefile, &mut localfile, |bytes| {
+            if show {
+                progress.update(bytes)
+            }
+        });
+        progress.finish();
+        let bytes = bytes?;
+        // local file closed after drop
+        drop(localfile);
+
+        if self.withxattr {
+            self.xattrs += pull_xattrs(client, remotepath, localpath)?;
+        }
+        self.files += 1;
+        self.bytes += bytes;
+        Ok(bytes)
+    }
+
+    fn put_file(&mut self, client: &SFFSClient, localpath: &Path, remotepath: &str) -> sffs::Result<u64> {
+        let mut localfile = File::open(localpath).map_err(|_| NotFound(localpath.display().to_string()))?;
+        let size = localfile.metadata().ok().map(|meta| meta.len());
+
+        // read local file to remote file
+        let mut progress = Progress::new(remotepath, size);
+        let show = self.progress;
+        let bytes = client.put_with_progress(&mut localfile, remotepath, |bytes| {
+            if show {
+                progress.update(bytes)
+            }
+        });
+        progress.finish();
+        let bytes = bytes?;
+        // local file closed after drop
+        drop(localfile);
+
+        if self.withxattr {
+            self.xattrs += push_xattrs(client, localpath, remotepath)?;
+        }
+        self.files += 1;
+        self.bytes += bytes;
+        Ok(bytes)
+    }
+
+    // reports a file that failed, the others still go ahead
+    fn fail(&mut self, cmd: &str, name: impl std::fmt::Display, e: impl std::fmt::Display) {
+        eprintln!("{} {} failed {}", cmd, name, e);
+        self.failed += 1;
+    }
+
+    // elapsed time and average throughput
+    fn throughput(&self) -> String {
+        let elapsed = self.start.elapsed().as_secs_f64();
+        let rate = self.bytes as f64 / elapsed.max(0.001);
+        format!("in {:.1}s at {}/s", elapsed, human_size(rate as i64))
+    }
+
+    // fails if any file did, so that a script can tell
+    fn print(&self, cmd: &str) -> sffs::Result<()> {
+        let outcome = if self.failed > 0 {
+            "completed with errors"
+        } else {
+            "succeeded"
         };
-        if reply.get_value() {
+        if self.withxattr {
+            println!(
+                "{} {} transferring {} files, {} bytes and {} xattrs {}, {} failed",
+                cmd,
+                outcome,
+                self.files,
+                self.bytes,
+                self.xattrs,
+                self.throughput(),
+                self.failed
+            );
+        } else {
+            println!(
+                "{} {} transferring {} files and {} bytes {}, {} failed",
+                cmd,
+                outcome,
+                self.files,
+                self.bytes,
+                self.throughput(),
+                self.failed
+            );
+        }
+
+        if self.failed > 0 {
+            Err(format!("on {} of {} files", self.failed, self.files + self.failed).into())
+        } else {
             Ok(())
+        }
+    }
+}
+
+// Mirrors the remote directory into the local one. A file that fails is
+// reported and counted, the rest of the tree is still transferred.
+fn get_tree(client: &SFFSClient, remoteroot: &str, localroot: &str, transfer: &mut Transfer) -> sffs::Result<()> {
+    // the find is drained first, when the server shares this filesystem and
+    // the target lies inside the source, the walk would also find the copies
+    let entries = client.find(remoteroot, None, 0, false)?.collect::<sffs::Result<Vec<_>>>()?;
+    fs::create_dir_all(localroot)?;
+
+    for entry in entries {
+        let remotepath = Path::new(remoteroot).join(entry.get_name());
+        let remotepath = remotepath.to_string_lossy(); // joined from UTF8 strings
+        let localpath = Path::new(localroot).join(entry.get_name());
+
+        let res = if entry.get_isdir() {
+            fs::create_dir_all(&localpath).map_err(Into::into)
         } else {
-            Err(sffs::CommonErrorKind::CloseFail.into())
+            transfer.get_file(client, &remotepath, &localpath).map(|_| ())
+        };
+        if let Err(e) = res {
+            transfer.fail("get", &remotepath, e);
+        }
+    }
+    Ok(())
+}
+
+// Mirrors the local directory into the remote one, creating remote
+// directories as needed. Entries other than files and directories are skipped.
+fn put_tree(client: &SFFSClient, localroot: &str, remoteroot: &str, transfer: &mut Transfer) -> sffs::Result<()> {
+    let walk = Walk::new(localroot, 0, false).map_err(|_| NotFound(localroot.to_owned()))?;
+    client.mkdir(remoteroot)?;
+
+    for (path, meta) in walk {
+        let localpath = Path::new(localroot).join(&path);
+        let remotepath = match protocol_string(Path::new(remoteroot).join(&path)) {
+            Ok(remotepath) => remotepath,
+            Err(e) => {
+                transfer.fail("put", localpath.display(), e);
+                continue;
+            }
+        };
+
+        let res = if meta.is_dir() {
+            client.mkdir(&remotepath)
+        } else if meta.is_file() {
+            transfer.put_file(client, &localpath, &remotepath).map(|_| ())
+        } else {
+            continue;
+        };
+        if let Err(e) = res {
+            transfer.fail("put", localpath.display(), e);
         }
     }
+    Ok(())
+}
+
+// streams the bytes from `start` up to `end` with positional reads
+fn copy_range(remotefile: &mut RemoteFile, start: u64, end: u64, out: &mut impl Write) -> sffs::Result<()> {
+    let mut pos = start;
+    while pos < end {
+        let count = ((end - pos) as usize).min(MAX_BLOCK_SIZE);
+        let data = remotefile.read_at(pos, count)?;
+        if data.is_empty() {
+            break; // the file shrank meanwhile
+        }
+        out.write_all(&data)?;
+        pos += data.len() as u64;
+    }
+    Ok(())
 }
 
-impl<'a> Drop for RemoteFile<'a> {
-    fn drop(&mut self) {
-        if let Err(e) = self.close() {
-            eprintln!("{}", e);
+// Takes `flag` if it is the next argument.
+fn take_flag<'a, I: Iterator<Item = &'a str>>(cmd_iter: &mut Peekable<I>, flag: &str) -> bool {
+    if cmd_iter.peek() == Some(&flag) {
+        cmd_iter.next();
+        true
+    } else {
+        false
+    }
+}
+
+// Offset where the last `lines` lines of a file of `size` bytes begin, found
+// by reading blocks backwards from the end so a long file is not read whole.
+// `read_at` gives the bytes of a range, at most MAX_BLOCK_SIZE of them.
+fn tail_start<F>(size: u64, lines: u64, mut read_at: F) -> sffs::Result<u64>
+where
+    F: FnMut(u64, usize) -> sffs::Result<Vec<u8>>,
+{
+    if lines == 0 {
+        return Ok(size);
+    }
+    let mut newlines = 0;
+    let mut end = size;
+    while end > 0 {
+        let start = end.saturating_sub(MAX_BLOCK_SIZE as u64);
+        let data = read_at(start, (end - start) as usize)?;
+        if let Some(pos) = block_tail_start(&data, start, size, lines, &mut newlines) {
+            return Ok(pos);
         }
+        end = start;
     }
+    Ok(0)
 }
 
-fn run_cmd(client: &SffsClient, cmd: &str, mut cmd_iter: std::str::SplitWhitespace) -> sffs::Result<()> {
+// One step of tail_start over the `block` at offset `start`, with `newlines`
+// carrying the count from the blocks after it. `lines` must not be 0.
+fn block_tail_start(block: &[u8], start: u64, size: u64, lines: u64, newlines: &mut u64) -> Option<u64> {
+    for (i, &b) in block.iter().enumerate().rev() {
+        let pos = start + i as u64;
+        // a newline ending the file does not begin another line
+        if b == b'\n' && pos + 1 != size {
+            *newlines += 1;
+            if *newlines == lines {
+                return Some(pos + 1);
+            }
+        }
+    }
+    None
+}
+
+// How much of `block` head prints while `left` lines remain, counting them down.
+fn head_len(block: &[u8], left: &mut u64) -> usize {
+    if *left == 0 {
+        return 0;
+    }
+    for (i, _) in block.iter().enumerate().filter(|&(_, &b)| b == b'\n') {
+        *left -= 1;
+        if *left == 0 {
+            return i + 1;
+        }
+    }
+    block.len()
+}
+
+fn run_cmd<'a>(
+    client: &SFFSClient,
+    progress: bool,
+    cmd: &str,
+    mut cmd_iter: impl Iterator<Item = &'a str>,
+) -> sffs::Result<()> {
     match cmd {
         "getdir" | "pwd" => {
-            let is_found = client.getdir(&ffs::Void::new())?;
-            println!("getdir succeeded with {}", is_found.get_value());
+            let dir = client.getdir()?;
+            println!("getdir succeeded with {}", dir);
         }
         "cd" => {
             // cd directory_name
             let path = cmd_iter.next().ok_or(InvalidArgument)?;
 
-            let is_success = client.changedir(&path.into())?;
-            if is_success.get_value() {
-                println!("cd succeeded");
-            } else {
-                println!("cd failed");
+            client.cd(path)?;
+            println!("cd succeeded");
+        }
+        "lcd" => {
+            // lcd directory_name, local paths are resolved against it from then on
+            let path = cmd_iter.next().ok_or(InvalidArgument)?;
+
+            env::set_current_dir(path).map_err(|_| NotFound(path.to_owned()))?;
+            println!("lcd succeeded");
+        }
+        "lpwd" => {
+            let dir = env::current_dir()?;
+            println!("lpwd succeeded with {}", dir.display());
+        }
+        "lls" => {
+            // lls [-l] [option] [directory_name], the filter is checked here
+            let mut cmd_iter = cmd_iter.peekable();
+            let longopt = take_flag(&mut cmd_iter, "-l");
+            let (path, filter) = path_and_filter(&mut cmd_iter)?;
+            let filter = match filter {
+                Some(filter) => filter.resolve()?,
+                None => MetaDataFilter::Empty,
+            };
+
+            // ".", ".." and then the directory entries, like the remote listing
+            let dir = Path::new(path);
+            // (name, path, follow), "." and ".." are the directories themselves
+            let mut paths = vec![
+                (".".to_owned(), dir.join("."), true),
+                ("..".to_owned(), dir.join(".."), true),
+            ];
+            for entry in fs::read_dir(dir).map_err(|_| NotFound(path.to_owned()))? {
+                let entry = entry?;
+                paths.push((entry.file_name().to_string_lossy().into_owned(), entry.path(), false));
+            }
+
+            let mut entries = Vec::new();
+            for (name, path, follow) in paths {
+                // a symlink among the entries is listed as itself
+                let meta = if follow {
+                    fs::metadata(&path)
+                } else {
+                    fs::symlink_metadata(&path)
+                };
+                let meta = match meta {
+                    Ok(meta) => meta,
+                    Err(_) => continue,
+                };
+                if filter.check(&name, &meta) {
+                    if let Ok(entry) = ffs::DirEntry::try_from((name, meta)) {
+                        entries.push(entry);
+                    }
+                }
             }
+            entries.sort_by(|a, b| a.get_name().cmp(b.get_name()));
+            for entry in &entries {
+                print_entry(entry, longopt);
+            }
+        }
+        "mkdir" => {
+            // mkdir directory_name
+            let path = cmd_iter.next().ok_or(InvalidArgument)?;
+
+            client.mkdir(path)?;
+            println!("mkdir succeeded");
+        }
+        "stat" => {
+            // stat remotepath
+            let path = cmd_iter.next().ok_or(InvalidArgument)?;
+
+            let entry = client.stat(path)?;
+            print_entry(&entry, true);
         }
         "filecount" => {
-            let mut option = ffs::ListOption::new();
-            if let Some(o) = cmd_iter.next() {
-                option.set_option(o.to_owned());
+            let mut cmd_iter = cmd_iter.peekable();
+            let withstats = take_flag(&mut cmd_iter, "-s");
+            let (path, option) = path_and_option(&mut cmd_iter)?;
+
+            let mut request = ffs::CountRequest::new();
+            request.set_dir(path.to_owned());
+            request.set_stats(withstats);
+            if let Some(mut option) = option {
+                request.set_option(option.take_option());
+                request.set_filter(option.take_filter());
+            }
+
+            let reply = client.filecount(&request)?;
+            println!("filecount succeeded with count of {}", reply.get_count());
+            if withstats {
+                for (kind, stats) in [("files", reply.get_files()), ("dirs", reply.get_dirs())].iter() {
+                    if stats.get_count() == 0 {
+                        println!("{}:\t0", kind);
+                        continue;
+                    }
+                    let oldest = Utc.timestamp(stats.get_oldest(), 0).format("%F %T %Z");
+                    let newest = Utc.timestamp(stats.get_newest(), 0).format("%F %T %Z");
+                    println!(
+                        "{}:\t{}\t{}\tlargest {} ({})\toldest {}\tnewest {}",
+                        kind,
+                        stats.get_count(),
+                        human_size(stats.get_bytes()),
+                        stats.get_largest(),
+                        human_size(stats.get_largestsize()),
+                        oldest,
+                        newest
+                    );
+                }
             }
-            let reply = client.filecount(&option)?;
-            println!("filecount succeeded with count of {}", reply.get_value());
         }
         "ls" => {
             let mut cmd_iter = cmd_iter.peekable();
-            let longopt = if let Some(&"-l") = cmd_iter.peek() {
-                cmd_iter.next();
-                true
-            } else {
-                false
-            };
-            let (path, option) = match cmd_iter.next() {
-                Some(token) => {
-                    if MetaDataFilter::is_valid_pattern(token) {
-                        (cmd_iter.next().unwrap_or("."), Some(token)) // token as option
-                    } else {
-                        (token, cmd_iter.next()) // token as path
+            let mut longopt = false;
+            let mut pagesize = None;
+            let mut sort = ffs::SortOption::new();
+            sort.set_key(ffs::SortKey::NAME);
+            // -S and -t list the largest and newest first like ls(1), -r flips that
+            let (mut descending, mut reverse) = (false, false);
+            while let Some(&flags) = cmd_iter.peek() {
+                if flags == "--dirs-first" {
+                    sort.set_dirsfirst(true);
+                } else if flags.starts_with("--page=") {
+                    let count = flags["--page=".len()..].parse::<i64>().map_err(|_| InvalidArgument)?;
+                    pagesize = Some(count);
+                } else if flags.starts_with('-') && flags.len() > 1 {
+                    for c in flags[1..].chars() {
+                        match c {
+                            'l' => longopt = true,
+                            'S' => {
+                                sort.set_key(ffs::SortKey::SIZE);
+                                descending = true;
+                            }
+                            't' => {
+                                sort.set_key(ffs::SortKey::MTIME);
+                                descending = true;
+                            }
+                            'U' => {
+                                sort.set_key(ffs::SortKey::NONE);
+                                descending = false;
+                            }
+                            'r' => reverse = true,
+                            _ => return Err(InvalidArgument.into()),
+                        }
                     }
+                } else {
+                    break;
                 }
-                None => (".", None), // there won't be another token afterwards
-            };
-            let option = option.map(|s| s.to_owned());
-
-            // open list
-            let mut remotelist = RemoteFile::openlist(client, path, option)?;
+                cmd_iter.next();
+            }
+            sort.set_reverse(descending != reverse);
+            let (path, option) = path_and_option(&mut cmd_iter)?;
 
-            // traverse list
-            loop {
-                let entry = client.nextlist(&ffs::Void::new())?;
-                if entry.get_name().is_empty() {
-                    break;
+            if let Some(pagesize) = pagesize {
+                let mut request = ffs::ListRequest::new();
+                request.set_dir(path.to_owned());
+                request.set_sort(sort);
+                request.set_limit(pagesize);
+                if let Some(option) = option {
+                    request.set_option(option);
                 }
 
-                print!("{}", entry.get_name());
-                if entry.get_isdir() { print!("/"); }
-                if longopt {
-                    let time = Utc
-                        .timestamp(entry.get_modifytime(), 0)
-                        .format("%a\t%b\t%d\t%T\t%Z\t%Y");
-                    print!("\t{}\t{}", entry.get_size(), time);
+                // fetch page by page, each one resuming from the cursor of the last
+                loop {
+                    let mut page = client.listpage(&request)?;
+                    for entry in page.get_entries() {
+                        print_entry(entry, longopt);
+                    }
+                    if page.get_cursor().is_empty() {
+                        break;
+                    }
+                    request.set_cursor(page.take_cursor());
                 }
-                print!("\n");
+                return Ok(());
             }
 
-            // close list
-            remotelist.close()?;
+            // traverse list, closed once exhausted
+            for entry in client.list(path, option, sort)? {
+                print_entry(&entry?, longopt);
+            }
         }
-        "get" => {
-            let remotepath = cmd_iter.next().ok_or(InvalidArgument)?;
-            let localpath = cmd_iter.next().unwrap_or(remotepath);
-
-            // open remote file
-            let mut remotefile = RemoteFile::open(client, remotepath)?;
+        "find" => {
+            let mut cmd_iter = cmd_iter.peekable();
+            let mut maxdepth = 0;
+            let mut follow = false;
+            loop {
+                match cmd_iter.peek() {
+                    Some(&"-L") => {
+                        cmd_iter.next();
+                        follow = true;
+                    }
+                    Some(&"-maxdepth") => {
+                        cmd_iter.next();
+                        let depth = cmd_iter.next().ok_or(InvalidArgument)?;
+                        maxdepth = depth.parse::<i32>().map_err(|_| InvalidArgument)?;
+                    }
+                    _ => break,
+                }
+            }
+            let (path, option) = path_and_option(&mut cmd_iter)?;
 
-            // open local file
-            let _ = unistd::unlink(localpath);
-            let mut localfile = File::create(localpath).map_err(|_| "cannot open local file as write")?;
+            // traverse matches, closed once exhausted
+            let mut count = 0;
+            for entry in client.find(path, option, maxdepth, follow)? {
+                println!("{}", Path::new(path).join(entry?.get_name()).display());
+                count += 1;
+            }
 
-            let mut bytes = 0usize;
-            // read remote data to local file
+            println!("find succeeded with {} matches", count);
+        }
+        "get" | "put" => {
+            let mut cmd_iter = cmd_iter.peekable();
+            let (mut withxattr, mut recursive) = (false, false);
             loop {
-                let reply = client.nextread(&ffs::Void::new())?;
-                if reply.get_data().is_empty() {
-                    break;
+                match cmd_iter.peek() {
+                    Some(&"-x") => withxattr = true,
+                    Some(&"-r") => recursive = true,
+                    _ => break,
                 }
-                localfile.write(reply.get_data())?;
-                bytes += reply.get_data().len();
+                cmd_iter.next();
             }
+            let source = cmd_iter.next().ok_or(InvalidArgument)?;
+            let target = cmd_iter.next().unwrap_or(source);
 
-            // close remote file
-            remotefile.close()?;
-            // local file closed after drop
-            drop(localfile);
+            let mut transfer = Transfer::new(withxattr, progress);
+            match (cmd, recursive) {
+                ("get", false) => transfer.get_file(client, source, Path::new(target)).map(|_| ())?,
+                ("put", false) => transfer.put_file(client, Path::new(source), target).map(|_| ())?,
+                ("get", true) => get_tree(client, source, target, &mut transfer)?,
+                _ => put_tree(client, source, target, &mut transfer)?,
+            }
 
-            println!("get succeeded transferring {} bytes", bytes);
+            if recursive {
+                transfer.print(cmd)?;
+            } else if withxattr {
+                println!(
+                    "{} succeeded transferring {} bytes and {} xattrs {}",
+                    cmd,
+                    transfer.bytes,
+                    transfer.xattrs,
+                    transfer.throughput()
+                );
+            } else {
+                println!("{} succeeded transferring {} bytes {}", cmd, transfer.bytes, transfer.throughput());
+            }
         }
-        "put" => {
-            let localpath = cmd_iter.next().ok_or(InvalidArgument)?;
-            let remotepath = cmd_iter.next().unwrap_or(localpath);
-
-            let mut localfile = File::open(localpath).map_err(|_| NotFound(localpath.to_owned()))?;
+        "mget" => {
+            // mget [-x] remotepattern [localdir]
+            let mut cmd_iter = cmd_iter.peekable();
+            let withxattr = take_flag(&mut cmd_iter, "-x");
+            let pattern = cmd_iter.next().ok_or(InvalidArgument)?;
+            let localdir = cmd_iter.next().unwrap_or(".");
 
-            // open remote file
-            let mut remotefile = RemoteFile::create(client, remotepath)?;
+            // the glob only applies to the last component, like name= in ls
+            let (dir, glob) = match pattern.rfind('/') {
+                Some(0) => ("/", &pattern[1..]),
+                Some(i) => (&pattern[..i], &pattern[i + 1..]),
+                None => (".", pattern),
+            };
+            let filter = MetaDataFilter::And(
+                Box::new(MetaDataFilter::Name(Pattern::new(glob).map_err(|_| InvalidArgument)?)),
+                Box::new(MetaDataFilter::Type(false)),
+            );
+            // typed only, pasting the glob into a filter string could change its meaning
+            let mut option = ffs::ListOption::new();
+            option.set_filter((&filter).into());
+            let mut sort = ffs::SortOption::new();
+            sort.set_key(ffs::SortKey::NAME);
 
-            let mut bytes = 0usize;
-            // read local file to remote file
-            loop {
-                let mut buf = vec![0u8; MAX_BLOCK_SIZE];
+            // the names are collected first, so that a listing error stops
+            // mget before anything is fetched
+            let names = (client.list(dir, Some(option), sort)?)
+                .map(|entry| entry.map(|mut entry| entry.take_name()))
+                .collect::<sffs::Result<Vec<_>>>()?;
 
-                let len = localfile.read(&mut buf).unwrap_or(0);
-                if len == 0 {
-                    break;
+            let mut transfer = Transfer::new(withxattr, progress);
+            for name in names {
+                let remotepath = Path::new(dir).join(&name);
+                let remotepath = remotepath.to_string_lossy(); // joined from UTF8 strings
+                match transfer.get_file(client, &remotepath, &Path::new(localdir).join(&name)) {
+                    Ok(bytes) => println!("get {} succeeded transferring {} bytes", remotepath, bytes),
+                    Err(e) => transfer.fail("get", &remotepath, e),
                 }
-                buf.truncate(len);
+            }
+            transfer.print(cmd)?;
+        }
+        "mput" => {
+            // mput [-x] localpattern [remotedir]
+            let mut cmd_iter = cmd_iter.peekable();
+            let withxattr = take_flag(&mut cmd_iter, "-x");
+            let pattern = cmd_iter.next().ok_or(InvalidArgument)?;
+            let remotedir = cmd_iter.next().unwrap_or(".");
 
-                if client.nextwrite(&buf.into())?.get_value() {
-                    bytes += len;
-                } else {
-                    eprintln!("put failed: cannot write remote file");
+            let mut transfer = Transfer::new(withxattr, progress);
+            for localpath in glob::glob(pattern).map_err(|_| InvalidArgument)? {
+                let localpath = match localpath {
+                    Ok(localpath) => localpath,
+                    Err(e) => {
+                        transfer.fail("put", e.path().display(), e.error());
+                        continue;
+                    }
+                };
+                if !localpath.is_file() {
+                    continue;
+                }
+                let name = localpath.file_name().unwrap_or_default();
+                let remotepath = match protocol_string(Path::new(remotedir).join(name)) {
+                    Ok(remotepath) => remotepath,
+                    Err(e) => {
+                        transfer.fail("put", localpath.display(), e);
+                        continue;
+                    }
+                };
+                match transfer.put_file(client, &localpath, &remotepath) {
+                    Ok(bytes) => println!("put {} succeeded transferring {} bytes", localpath.display(), bytes),
+                    Err(e) => transfer.fail("put", localpath.display(), e),
                 }
             }
-
-            // close remote file
-            remotefile.close()?;
-            // local file closed after drop
-            drop(localfile);
-
-            println!("put succeeded transferring {} bytes", bytes);
+            transfer.print(cmd)?;
         }
         "randomread" => {
             use InvalidArgument as InvArg;
             let remotepath = cmd_iter.next().ok_or(InvArg)?;
-            let range_start = cmd_iter.next().ok_or(InvArg)?.parse::<i64>().map_err(|_| InvArg)?;
+            let range_start = cmd_iter.next().ok_or(InvArg)?.parse::<u64>().map_err(|_| InvArg)?;
             let range_count = cmd_iter.next().ok_or(InvArg)?.parse::<i64>().map_err(|_| InvArg)?;
 
             if !(0 <= range_count && range_count as usize <= MAX_BLOCK_SIZE) {
                 return Err(InvArg.into());
             }
 
-            // open remote file
-            let mut remotefile = RemoteFile::open(client, remotepath)?;
             // read remote data to stdout
-            let reply = client.randomread(&(range_start, range_count).into())?;
-            // close remote file
-            remotefile.close()?;
+            let data = client.randomread(remotepath, range_start, range_count as usize)?;
+
+            println!("randomread succeeded transferring {} bytes", data.len());
+            print!("{}", String::from_utf8_lossy(&data));
+        }
+        "cat" => {
+            // cat remotefile...
+            let stdout = io::stdout();
+            let mut out = stdout.lock();
+
+            let mut count = 0;
+            for remotepath in cmd_iter {
+                client.get(remotepath, &mut out)?;
+                count += 1;
+            }
+            out.flush()?;
+            if count == 0 {
+                return Err(InvalidArgument.into());
+            }
+        }
+        "head" | "tail" => {
+            use InvalidArgument as InvArg;
+            let mut cmd_iter = cmd_iter.peekable();
+            // -n counts lines and -c bytes, 10 lines by default
+            let (mut count, mut bylines) = (10, true);
+            loop {
+                match cmd_iter.peek() {
+                    Some(&"-n") => bylines = true,
+                    Some(&"-c") => bylines = false,
+                    _ => break,
+                }
+                cmd_iter.next();
+                count = cmd_iter.next().ok_or(InvArg)?.parse::<u64>().map_err(|_| InvArg)?;
+            }
+            let remotepath = cmd_iter.next().ok_or(InvArg)?;
+
+            let stdout = io::stdout();
+            let mut out = stdout.lock();
+            if cmd == "head" {
+                let mut remotefile = client.open(remotepath)?;
+                let mut left = count;
+                while left > 0 {
+                    let data = remotefile.read()?;
+                    if data.is_empty() {
+                        break;
+                    }
+                    let len = if bylines {
+                        head_len(&data, &mut left)
+                    } else {
+                        let len = data.len().min(left as usize);
+                        left -= len as u64;
+                        len
+                    };
+                    out.write_all(&data[..len])?;
+                }
+                // stops early, the rest of the file is never read
+                remotefile.close()?;
+            } else {
+                let size = client.stat(remotepath)?.get_size() as u64;
+                let mut remotefile = client.open(remotepath)?;
+                let start = if bylines {
+                    tail_start(size, count, |start, len| remotefile.read_at(start, len))?
+                } else {
+                    size.saturating_sub(count)
+                };
+                copy_range(&mut remotefile, start, size, &mut out)?;
+                remotefile.close()?;
+            }
+            out.flush()?;
+        }
+        "lsxattr" => {
+            let remotepath = cmd_iter.next().ok_or(InvalidArgument)?;
+
+            for name in client.listxattr(remotepath)? {
+                println!("{}", name);
+            }
+        }
+        "getxattr" => {
+            let remotepath = cmd_iter.next().ok_or(InvalidArgument)?;
+            let name = cmd_iter.next().ok_or(InvalidArgument)?;
+
+            let value = client.getxattr(remotepath, name)?.ok_or_else(|| NotFound(name.to_owned()))?;
+            println!("getxattr succeeded with {}", String::from_utf8_lossy(&value));
+        }
+        "setxattr" => {
+            let remotepath = cmd_iter.next().ok_or(InvalidArgument)?;
+            let name = cmd_iter.next().ok_or(InvalidArgument)?;
+            let value = cmd_iter.next().unwrap_or("");
+
+            if client.setxattr(remotepath, name, value.as_bytes().to_vec())? {
+                println!("setxattr succeeded");
+            } else {
+                println!("setxattr failed");
+            }
+        }
+        "rmxattr" => {
+            let remotepath = cmd_iter.next().ok_or(InvalidArgument)?;
+            let name = cmd_iter.next().ok_or(InvalidArgument)?;
 
-            println!("randomread succeeded transferring {} bytes", reply.get_data().len());
-            print!("{}", String::from_utf8_lossy(&reply.get_data()));
+            if client.removexattr(remotepath, name)? {
+                println!("rmxattr succeeded");
+            } else {
+                println!("rmxattr failed");
+            }
+        }
+        "ln" => {
+            let source = cmd_iter.next().ok_or(InvalidArgument)?;
+            let target = cmd_iter.next().ok_or(InvalidArgument)?;
+
+            match client.link(source, target)? {
+                Some(nlink) => println!("ln succeeded with link count of {}", nlink),
+                None => println!("ln failed"),
+            }
+        }
+        "df" => {
+            let path = cmd_iter.next().unwrap_or(".");
+
+            let st = client.statfs(path)?;
+            let used = st.get_totalbytes() - st.get_freebytes();
+            let iused = st.get_totalinodes() - st.get_freeinodes();
+
+            println!("Size\tUsed\tAvail\tUse%\tInodes\tIUsed\tIAvail\tIUse%");
+            println!(
+                "{}\t{}\t{}\t{}%\t{}\t{}\t{}\t{}%",
+                human_size(st.get_totalbytes()),
+                human_size(used),
+                human_size(st.get_availbytes()),
+                percentage(used, used + st.get_availbytes()),
+                st.get_totalinodes(),
+                iused,
+                st.get_availinodes(),
+                percentage(iused, iused + st.get_availinodes()),
+            );
+        }
+        "du" => {
+            let mut cmd_iter = cmd_iter.peekable();
+            let perchild = take_flag(&mut cmd_iter, "-c");
+            let (path, option) = path_and_option(&mut cmd_iter)?;
+
+            let mut request = ffs::DiskUsageRequest::new();
+            request.set_dir(path.to_owned());
+            request.set_perchild(perchild);
+            if let Some(option) = option {
+                request.set_option(option);
+            }
+
+            let usage = client.diskusage(&request)?;
+            // used on disk, then the apparent size
+            for child in usage.get_children() {
+                let (used, bytes) = (child.get_used(), child.get_bytes());
+                let (files, dirs) = (child.get_files(), child.get_dirs());
+                println!("{}\t{}\t{}\t{}\t{}", human_size(used), human_size(bytes), files, dirs, child.get_name());
+            }
+            println!(
+                "du succeeded with {} used ({} apparent) in {} files and {} directories",
+                human_size(usage.get_used()),
+                human_size(usage.get_bytes()),
+                usage.get_files(),
+                usage.get_dirs()
+            );
+        }
+        "sum" => {
+            use InvalidArgument as InvArg;
+            let mut cmd_iter = cmd_iter.peekable();
+            let mut algorithm = ffs::ChecksumAlgorithm::SHA256;
+            let mut range = None;
+            loop {
+                match cmd_iter.peek() {
+                    Some(&"-a") => {
+                        cmd_iter.next();
+                        algorithm = checksum::parse_algorithm(cmd_iter.next().ok_or(InvArg)?).ok_or(InvArg)?;
+                    }
+                    Some(&"-r") => {
+                        cmd_iter.next();
+                        let start = cmd_iter.next().ok_or(InvArg)?.parse::<u64>().map_err(|_| InvArg)?;
+                        let count = cmd_iter.next().ok_or(InvArg)?.parse::<u64>().map_err(|_| InvArg)?;
+                        range = Some((start, count));
+                    }
+                    _ => break,
+                }
+            }
+            let remotepath = cmd_iter.next().ok_or(InvArg)?;
+            let localpath = cmd_iter.next();
+
+            let mut request = ffs::ChecksumRequest::new();
+            request.set_path(remotepath.to_owned());
+            request.set_algorithm(algorithm);
+            if let Some((start, count)) = range {
+                request.set_range((start as i64, count as i64).into());
+            }
+
+            let reply = client.checksum(&request)?;
+            if !reply.get_found() {
+                return Err(NotFound(remotepath.to_owned()).into());
+            }
+            println!("{}  {}", reply.get_digest(), remotepath);
+
+            if let Some(localpath) = localpath {
+                let mut localfile = File::open(localpath).map_err(|_| NotFound(localpath.to_owned()))?;
+                let (digest, _) = checksum::digest_file(&mut localfile, algorithm, range)?;
+                println!("{}  {}", digest, localpath);
+
+                if digest == reply.get_digest() {
+                    println!("sum succeeded, files match");
+                } else {
+                    println!("sum succeeded, files differ");
+                }
+            }
         }
         c => eprintln!("Unknown command: {:?}", c),
     }
@@ -252,21 +1315,16 @@ fn main() {
     let mut args = std::env::args();
     let prog_name = args.next().expect("Cannot get program name");
 
-    let mut addr = args.next().unwrap_or_else(|| usage(&prog_name));
-    addr.push_str(":");
-    addr.push_str(&common::COMM_PORT.to_string());
+    let host = args.next().unwrap_or_else(|| usage(&prog_name));
+    let client = SFFSClient::connect(&host);
 
-    let env = Arc::new(EnvBuilder::new().build());
-    let ch = ChannelBuilder::new(env).connect(&addr);
-    let client = SffsClient::new(ch);
-
-    let mut inputstream: Box<dyn BufRead>;
+    let mut input: Input;
     let isfile: bool;
     if let Some(o) = args.next() {
         if o == "-f" {
             if let Some(filename) = args.next() {
                 let file = File::open(filename).expect(&format!("Cannot open script file {}", prog_name));
-                inputstream = Box::new(BufReader::new(file));
+                input = Input::Script(Box::new(BufReader::new(file)));
                 isfile = true;
             } else {
                 usage(&prog_name);
@@ -275,38 +1333,230 @@ fn main() {
             usage(&prog_name);
         }
     } else {
-        inputstream = Box::new(BufReader::new(io::stdin()));
+        input = Input::repl(&client, &host);
         isfile = false;
     };
+    // a status line would only clutter a log or a pipe
+    let progress = !isfile && unistd::isatty(io::stderr().as_raw_fd()).unwrap_or(false);
 
-    // for cmdline in io::stdin().lock().lines() {
-    loop {
-        if !isfile {
-            prompt();
-        }
-
-        let mut cmdline = String::new();
-        inputstream.read_line(&mut cmdline).expect("Could not read a line!");
-
-        // EOF (with no '\n')
-        if cmdline.is_empty() {
-            break;
-        }
-
-        let mut cmdline_iter = cmdline.split_whitespace();
+    // a script exits with 1 if any of its commands failed
+    let mut status = 0;
+    while let Some(cmdline) = input.next_line() {
+        let args = match split_args(&cmdline) {
+            Ok(args) => args,
+            Err(e) => {
+                eprintln!("Invalid command line: {}", e);
+                status = 1;
+                continue;
+            }
+        };
+        let mut cmdline_iter = args.iter().map(String::as_str);
         let cmd = match cmdline_iter.next() {
             Some(cmd) => cmd,
             None => continue, //continue when command line is empty
         };
 
-        if let Err(e) = run_cmd(&client, &cmd, cmdline_iter) {
+        let result = run_cmd(&client, progress, cmd, cmdline_iter);
+        if result.is_ok() && cmd == "cd" {
+            input.refresh_cwd(&client);
+        }
+        if let Err(e) = result {
             use sffs::ExecuteError::{Common, Custom, IO, RPC};
+            status = 1;
             match e {
                 IO(e) => eprintln!("{} failed with I/O Error {}", cmd, e),
+                // the server tells why, e.g. a malformed filter
+                RPC(grpcio::Error::RpcFailure(RpcStatus {
+                    status: RpcStatusCode::InvalidArgument,
+                    details: Some(reason),
+                    ..
+                })) => eprintln!("{} failed {}", cmd, reason),
                 RPC(e) => eprintln!("{} failed with RPC Error {}", cmd, e),
                 Common(e) => eprintln!("{} failed {}", cmd, e),
                 Custom(e) => eprintln!("{} failed {}", cmd, e),
             }
         }
     }
+
+    if isfile {
+        std::process::exit(status);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn split(line: &str) -> Vec<String> {
+        split_args(line).unwrap()
+    }
+
+    #[test]
+    fn split_plain_words() {
+        assert_eq!(split("  ls  -l\tdir "), ["ls", "-l", "dir"]);
+        assert!(split("   ").is_empty());
+    }
+
+    #[test]
+    fn split_single_quotes_keep_everything() {
+        assert_eq!(split(r"ls 'a b\c\'"), ["ls", r"a b\c\"]);
+        assert_eq!(split("ls 'name~(a|b)'"), ["ls", "name~(a|b)"]);
+    }
+
+    #[test]
+    fn split_double_quotes() {
+        assert_eq!(split(r#"get "a b" c"#), ["get", "a b", "c"]);
+        assert_eq!(split(r#"get "say \"hi\"""#), ["get", r#"say "hi""#]);
+        assert_eq!(split(r#"get "a\\b\d""#), ["get", r"a\b\d"]);
+    }
+
+    #[test]
+    fn split_escaped_space() {
+        assert_eq!(split(r"get my\ file x"), ["get", "my file", "x"]);
+        assert_eq!(split(r"ls name~a\.b"), ["ls", "name~a.b"]);
+    }
+
+    #[test]
+    fn split_quotes_join_a_word() {
+        assert_eq!(split(r#"get a'b c'"d e"f"#), ["get", "ab cd ef"]);
+    }
+
+    #[test]
+    fn split_empty_quotes_are_an_argument() {
+        assert_eq!(split("cd ''"), ["cd", ""]);
+        assert_eq!(split(r#"cd """#), ["cd", ""]);
+    }
+
+    #[test]
+    fn split_comments() {
+        assert_eq!(split("# nothing here"), Vec::<String>::new());
+        assert_eq!(split("ls dir # the rest"), ["ls", "dir"]);
+        assert_eq!(split("ls a#b"), ["ls", "a#b"]);
+        assert_eq!(split(r"ls \#b '#c'"), ["ls", "#b", "#c"]);
+    }
+
+    #[test]
+    fn split_errors() {
+        assert_eq!(split_args("ls 'a"), Err("unterminated single quote"));
+        assert_eq!(split_args(r#"ls "a"#), Err("unterminated double quote"));
+        assert_eq!(split_args(r#"ls "a\"#), Err("unterminated double quote"));
+        assert_eq!(split_args(r"ls a\"), Err("trailing backslash"));
+    }
+
+    fn last(line: &str) -> (usize, String) {
+        last_word(line).unwrap()
+    }
+
+    #[test]
+    fn last_word_after_whitespace() {
+        assert_eq!(last(""), (0, "".to_owned()));
+        assert_eq!(last("get dir/fi"), (4, "dir/fi".to_owned()));
+        assert_eq!(last("get a "), (6, "".to_owned()));
+    }
+
+    #[test]
+    fn last_word_is_unquoted() {
+        assert_eq!(last(r"get my\ fi"), (4, "my fi".to_owned()));
+        assert_eq!(last("get 'my fi"), (4, "my fi".to_owned()));
+        assert_eq!(last(r#"get "a\"b"#), (4, r#"a"b"#.to_owned()));
+        assert_eq!(last(r"get a\"), (4, "a".to_owned()));
+    }
+
+    #[test]
+    fn last_word_in_comment() {
+        assert_eq!(last_word("ls # di"), None);
+        assert_eq!(last("ls a#b"), (3, "a#b".to_owned()));
+    }
+
+    #[test]
+    fn transfer_sides() {
+        assert!(is_local_arg("put", 0));
+        assert!(!is_local_arg("put", 1));
+        assert!(is_local_arg("mput", 0));
+        assert!(!is_local_arg("get", 0));
+        assert!(is_local_arg("get", 1));
+        assert!(is_local_arg("lls", 1));
+        assert!(!is_local_arg("ls", 0));
+    }
+
+    // head over `data` in blocks of `block` bytes, as the remote file reads them
+    fn head(data: &[u8], block: usize, lines: u64) -> Vec<u8> {
+        let mut out = Vec::new();
+        let mut left = lines;
+        for chunk in data.chunks(block) {
+            if left == 0 {
+                break;
+            }
+            out.extend_from_slice(&chunk[..head_len(chunk, &mut left)]);
+        }
+        out
+    }
+
+    // tail_start over `data` held locally
+    fn tail(data: &[u8], lines: u64) -> usize {
+        let read_at = |start: u64, len: usize| {
+            assert!(len <= MAX_BLOCK_SIZE);
+            Ok(data[start as usize..start as usize + len].to_vec())
+        };
+        tail_start(data.len() as u64, lines, read_at).unwrap() as usize
+    }
+
+    #[test]
+    fn head_trailing_newline() {
+        assert_eq!(head(b"a\nb\nc\n", 4, 2), b"a\nb\n");
+        assert_eq!(head(b"a\nb\nc\n", 1, 3), b"a\nb\nc\n");
+    }
+
+    #[test]
+    fn head_no_final_newline() {
+        assert_eq!(head(b"a\nb\nc", 2, 3), b"a\nb\nc");
+        assert_eq!(head(b"abc", 2, 1), b"abc");
+    }
+
+    #[test]
+    fn head_zero_and_more_than_there_are() {
+        assert_eq!(head(b"a\nb\n", 4, 0), b"");
+        assert_eq!(head(b"a\nb\n", 4, 10), b"a\nb\n");
+    }
+
+    #[test]
+    fn tail_trailing_newline() {
+        assert_eq!(tail(b"a\nb\nc\n", 1), 4);
+        assert_eq!(tail(b"a\nb\nc\n", 2), 2);
+    }
+
+    #[test]
+    fn tail_no_final_newline() {
+        assert_eq!(tail(b"a\nb\nc", 1), 4);
+        assert_eq!(tail(b"a\nb\nc", 2), 2);
+        assert_eq!(tail(b"abc", 1), 0);
+    }
+
+    #[test]
+    fn tail_zero_and_more_than_there_are() {
+        assert_eq!(tail(b"a\nb\n", 0), 4);
+        assert_eq!(tail(b"a\nb\n", 10), 0);
+        assert_eq!(tail(b"", 3), 0);
+    }
+
+    #[test]
+    fn newline_on_block_boundary() {
+        // blocks are read backwards from the end, the last one starts at MAX_BLOCK_SIZE
+        let mut data = vec![b'x'; 2 * MAX_BLOCK_SIZE];
+        *data.last_mut().unwrap() = b'\n';
+
+        data[MAX_BLOCK_SIZE - 1] = b'\n';
+        assert_eq!(tail(&data, 1), MAX_BLOCK_SIZE);
+        assert_eq!(tail(&data, 2), 0);
+
+        data[MAX_BLOCK_SIZE - 1] = b'x';
+        data[MAX_BLOCK_SIZE] = b'\n';
+        assert_eq!(tail(&data, 1), MAX_BLOCK_SIZE + 1);
+
+        // head reads forward, a line ending a block stops it there
+        data[MAX_BLOCK_SIZE] = b'x';
+        data[MAX_BLOCK_SIZE - 1] = b'\n';
+        assert_eq!(head(&data, MAX_BLOCK_SIZE, 1).len(), MAX_BLOCK_SIZE);
+        assert_eq!(head(&data, MAX_BLOCK_SIZE, 2).len(), 2 * MAX_BLOCK_SIZE);
+    }
 }
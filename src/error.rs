@@ -1,3 +1,5 @@
+use crate::filter::FilterError;
+
 use std::error::Error;
 use std::fmt;
 use std::io;
@@ -64,6 +66,13 @@ impl From<CommonErrorKind> for ExecuteError {
     }
 }
 
+impl From<FilterError> for ExecuteError {
+    #[inline]
+    fn from(e: FilterError) -> Self {
+        ExecuteError::Custom(Box::new(e))
+    }
+}
+
 impl From<&'static str> for ExecuteError {
     #[inline]
     fn from(s: &'static str) -> Self {
@@ -1,5 +1,13 @@
+use chrono::prelude::*;
+use glob::Pattern;
+use regex::Regex;
+
 use std::cmp::Ordering;
+use std::error::Error;
+use std::fmt;
 use std::fs::Metadata;
+use std::os::unix::fs::MetadataExt;
+use std::time::UNIX_EPOCH;
 
 #[derive(Clone, Copy)]
 pub enum TimeUnit {
@@ -10,23 +18,282 @@ pub enum TimeUnit {
 }
 
 #[derive(Clone, Copy)]
+pub enum Comparison {
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+}
+
+impl Comparison {
+    // Split the leading operator off `s`. Two-character operators go first.
+    fn parse(s: &str) -> Option<(Self, &str)> {
+        use Comparison::*;
+        const OPERATORS: [(&str, Comparison); 6] = [
+            (">=", GreaterEqual),
+            ("<=", LessEqual),
+            ("!=", NotEqual),
+            ("=", Equal),
+            ("<", Less),
+            (">", Greater),
+        ];
+        OPERATORS
+            .iter()
+            .find(|(op, _)| s.starts_with(op))
+            .map(|&(op, cmp)| (cmp, &s[op.len()..]))
+    }
+
+    #[inline]
+    pub fn test<T: Ord>(self, lhs: T, rhs: T) -> bool {
+        use Comparison::*;
+        let ord = lhs.cmp(&rhs);
+        match self {
+            Equal => ord == Ordering::Equal,
+            NotEqual => ord != Ordering::Equal,
+            Less => ord == Ordering::Less,
+            LessEqual => ord != Ordering::Greater,
+            Greater => ord == Ordering::Greater,
+            GreaterEqual => ord != Ordering::Less,
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub enum PermMatch {
+    Exact, // perm=MODE, exactly these bits
+    All,   // perm-MODE, all of these bits set
+    Any,   // perm/MODE, any of these bits set
+}
+
+#[derive(Clone)]
 pub enum MetaDataFilter {
-    Empty,                        // empty filter, always true
-    Size(Ordering, u64),          // file size
-    Age(Ordering, u64, TimeUnit), // current - last modified time
-    Type(bool),                   // true means dir
+    Empty,                          // empty filter, always true
+    Size(Comparison, u64),          // file size
+    Age(Comparison, u64, TimeUnit), // current - last modified time
+    MTime(Comparison, i64),         // last modified time, seconds since epoch
+    Type(bool),                     // true means dir
+    Perm(PermMatch, u32),           // permission bits, including suid, sgid and sticky
+    Uid(Comparison, u32),           // owner
+    Gid(Comparison, u32),           // group
+    User(Comparison, String),       // owner by name, becomes Uid once resolved
+    Group(Comparison, String),      // group by name, becomes Gid once resolved
+    Name(Pattern),                  // shell-style glob on the entry name
+    NameRegex(Regex),               // regular expression on the entry name
+    And(Box<MetaDataFilter>, Box<MetaDataFilter>),
+    Or(Box<MetaDataFilter>, Box<MetaDataFilter>),
+    Not(Box<MetaDataFilter>),
 }
 
-impl MetaDataFilter {
-    pub fn new(pattern: &str) -> Option<Self> {
-        #[inline]
-        fn to_ordering(c: char) -> Option<Ordering> {
-            match c {
-                '=' => Some(Ordering::Equal),
-                '<' => Some(Ordering::Less),
-                '>' => Some(Ordering::Greater),
-                _ => None,
+// Where and why a filter pattern was rejected.
+#[derive(Debug, Clone)]
+pub struct FilterError {
+    pub pos: usize, // character offset into the pattern
+    pub expected: String,
+    pub found: String, // offending text, empty at the end of the pattern
+}
+
+impl fmt::Display for FilterError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid filter at position {}: expected {}, ", self.pos, self.expected)?;
+        if self.found.is_empty() {
+            write!(f, "found end of filter")
+        } else {
+            write!(f, "found {:?}", self.found)
+        }
+    }
+}
+
+impl Error for FilterError {}
+
+// Byte offset into a predicate and what was expected there
+type PredicateError = (usize, String);
+
+// Recursive descent over the expression grammar, loosest binding first:
+//   or      := and ('|' and)*
+//   and     := not ('&' not)*
+//   not     := '!' not | primary
+//   primary := '(' or ')' | predicate
+struct Parser<'a> {
+    pattern: &'a str,
+    rem: &'a str,
+}
+
+// A predicate extends up to the next operator, except within a regular
+// expression after `~`: a `~/.../` literal may contain any of them, and a
+// bare one keeps `|` and parentheses that it balances itself.
+fn predicate_len(s: &str) -> usize {
+    let mut chars = s.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '&' | '|' | '(' | ')' => return i,
+            '~' if chars.peek().map(|&(_, c)| c) == Some('/') => {
+                chars.next();
+                let mut escaped = false;
+                for (_, c) in &mut chars {
+                    match c {
+                        _ if escaped => escaped = false,
+                        '\\' => escaped = true,
+                        '/' => break,
+                        _ => {}
+                    }
+                }
+            }
+            '~' => {
+                let (mut depth, mut escaped, mut class) = (0, false, false);
+                for (i, c) in &mut chars {
+                    match c {
+                        _ if escaped => escaped = false,
+                        '\\' => escaped = true,
+                        _ if class => class = c != ']',
+                        '[' => class = true,
+                        '(' => depth += 1,
+                        ')' if depth > 0 => depth -= 1,
+                        '|' if depth > 0 => {}
+                        '&' | '|' | ')' => return i,
+                        _ => {}
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    s.len()
+}
+
+// Byte offset of the `/` closing the `/.../` literal that `s` starts with
+fn literal_end(s: &str) -> Option<usize> {
+    let mut escaped = false;
+    for (i, c) in s.char_indices().skip(1) {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '/' => return Some(i),
+            _ => {}
+        }
+    }
+    None
+}
+
+impl<'a> Parser<'a> {
+    #[inline]
+    fn eat(&mut self, c: char) -> bool {
+        self.rem = self.rem.trim_start();
+        if self.rem.starts_with(c) {
+            self.rem = &self.rem[c.len_utf8()..];
+            true
+        } else {
+            false
+        }
+    }
+
+    // `found` is the text at `at` up to the next operator
+    fn error(&self, at: &str, expected: &str) -> FilterError {
+        let offset = self.pattern.len() - at.len();
+        let found = match at.chars().next() {
+            Some(c) if "&|()".contains(c) => c.to_string(),
+            _ => at[..predicate_len(at)].trim_end().to_owned(),
+        };
+        FilterError {
+            pos: self.pattern[..offset].chars().count(),
+            expected: expected.to_owned(),
+            found,
+        }
+    }
+
+    fn or(&mut self) -> Result<MetaDataFilter, FilterError> {
+        let mut lhs = self.and()?;
+        while self.eat('|') {
+            lhs = MetaDataFilter::Or(Box::new(lhs), Box::new(self.and()?));
+        }
+        Ok(lhs)
+    }
+
+    fn and(&mut self) -> Result<MetaDataFilter, FilterError> {
+        let mut lhs = self.not()?;
+        while self.eat('&') {
+            lhs = MetaDataFilter::And(Box::new(lhs), Box::new(self.not()?));
+        }
+        Ok(lhs)
+    }
+
+    fn not(&mut self) -> Result<MetaDataFilter, FilterError> {
+        if self.eat('!') {
+            Ok(MetaDataFilter::Not(Box::new(self.not()?)))
+        } else {
+            self.primary()
+        }
+    }
+
+    fn primary(&mut self) -> Result<MetaDataFilter, FilterError> {
+        if self.eat('(') {
+            let inner = self.or()?;
+            if self.eat(')') {
+                Ok(inner)
+            } else {
+                Err(self.error(self.rem, "')'"))
             }
+        } else {
+            let start = self.rem;
+            let (predicate, rem) = self.rem.split_at(predicate_len(self.rem));
+            self.rem = rem;
+
+            MetaDataFilter::predicate(predicate.trim_end())
+                .map_err(|(offset, expected)| self.error(&start[offset..], &expected))
+        }
+    }
+}
+
+impl MetaDataFilter {
+    pub fn new(pattern: &str) -> Result<Self, FilterError> {
+        if pattern.trim().is_empty() {
+            return Ok(MetaDataFilter::Empty); // shortcut
+        }
+
+        let mut parser = Parser { pattern, rem: pattern };
+        let filter = parser.or()?;
+        if parser.rem.trim().is_empty() {
+            Ok(filter)
+        } else {
+            // trailing garbage such as an unmatched ')'
+            Err(parser.error(parser.rem, "'&', '|' or end of filter"))
+        }
+    }
+
+    // `name~/regex/`, or a bare regex up to the next `&`, `|` or `)` outside
+    // its own parentheses. At the client prompt the regex has to be quoted,
+    // e.g. 'name~app-\d{8}\.log(\.gz)?', or the shell-style tokenizer
+    // takes its backslashes as escapes.
+    fn name_regex(pattern: &str) -> Result<Self, regex::Error> {
+        let pattern = if pattern.len() >= 2 && pattern.starts_with('/') && pattern.ends_with('/') {
+            pattern[1..pattern.len() - 1].replace("\\/", "/")
+        } else {
+            pattern.to_owned()
+        };
+        Ok(MetaDataFilter::NameRegex(Regex::new(&pattern)?))
+    }
+
+    fn predicate(pattern: &str) -> Result<Self, PredicateError> {
+        // k, M, G, T are powers of 1000 (an optional trailing B is allowed),
+        // KiB, MiB, GiB, TiB are powers of 1024
+        fn to_size(s: &str) -> Option<u64> {
+            let (num, suffix) = s.split_at(s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len()));
+            let num = num.parse::<u64>().ok()?;
+
+            let mut suffix = suffix.chars();
+            let scale = match suffix.next() {
+                None => 1,
+                Some(c) => {
+                    let exp = "KMGT".find(c.to_ascii_uppercase())? as u32 + 1;
+                    match suffix.as_str() {
+                        "" | "B" => 1000u64.pow(exp),
+                        "iB" => 1024u64.pow(exp),
+                        _ => return None,
+                    }
+                }
+            };
+            num.checked_mul(scale)
         }
         #[inline]
         fn to_unit(c: char) -> Option<TimeUnit> {
@@ -39,60 +306,223 @@ impl MetaDataFilter {
             }
         }
 
-        if pattern == "" {
-            Some(MetaDataFilter::Empty) // shortcut
-        } else if pattern.starts_with("size") {
-            // remainder
-            let mut rem = pattern["size".len()..].chars();
+        // RFC 3339, or a date and optional time taken as UTC
+        fn to_timestamp(s: &str) -> Option<i64> {
+            if let Ok(time) = DateTime::parse_from_rfc3339(s) {
+                return Some(time.timestamp());
+            }
+            if let Ok(time) = NaiveDateTime::parse_from_str(s, "%Y-%m-%dT%H:%M:%S") {
+                return Some(time.timestamp());
+            }
+            let date = NaiveDate::parse_from_str(s, "%Y-%m-%d").ok()?;
+            Some(date.and_hms(0, 0, 0).timestamp())
+        }
+
+        // octal, or symbolic like chmod(1) e.g. `o+w`, `u=rwx,g+s`
+        fn to_mode(s: &str) -> Option<u32> {
+            if !s.is_empty() && s.chars().all(|c| c.is_digit(8)) {
+                return u32::from_str_radix(s, 8).ok().filter(|&mode| mode <= 0o7777);
+            }
+
+            let mut mode = 0;
+            for clause in s.split(',') {
+                let (who, perms) = clause.split_at(clause.find(|c: char| c == '+' || c == '=')?);
+                let mut mask = 0;
+                for c in who.chars() {
+                    mask |= match c {
+                        'u' => 0o4700,
+                        'g' => 0o2070,
+                        'o' => 0o1007,
+                        'a' => 0o7777,
+                        _ => return None,
+                    };
+                }
+                if who.is_empty() {
+                    mask = 0o7777;
+                }
+                let mut bits = 0;
+                for c in perms[1..].chars() {
+                    bits |= match c {
+                        'r' => 0o444,
+                        'w' => 0o222,
+                        'x' => 0o111,
+                        's' => 0o6000,
+                        't' => 0o1000,
+                        _ => return None,
+                    };
+                }
+                mode |= mask & bits;
+            }
+            Some(mode)
+        }
+
+        // split the comparison operator off `rem`, a remainder of `pattern`
+        fn operator<'a>(pattern: &str, rem: &'a str) -> Result<(Comparison, &'a str), PredicateError> {
+            Comparison::parse(rem).ok_or((pattern.len() - rem.len(), "comparison operator".to_owned()))
+        }
 
-            let ord = to_ordering(rem.next()?)?;
-            let size = rem.as_str().parse::<u64>().ok()?;
+        // offset of a remainder of `pattern`, for error reporting
+        let at = |rem: &str| pattern.len() - rem.len();
 
-            Some(MetaDataFilter::Size(ord, size))
+        if pattern.starts_with("size") {
+            // remainder
+            let (cmp, rem) = operator(pattern, &pattern["size".len()..])?;
+            let size = to_size(rem).ok_or((at(rem), "size such as 100, 10k or 4MiB".to_owned()))?;
+
+            Ok(MetaDataFilter::Size(cmp, size))
         } else if pattern.starts_with("age") {
-            let mut rem = pattern["age".len()..].chars();
+            let (cmp, rem) = operator(pattern, &pattern["age".len()..])?;
 
-            let ord = to_ordering(rem.next()?)?;
-            let unit = to_unit(rem.clone().last()?)?;
-            let rem = rem.as_str();
-            let age = rem.split_at(rem.as_bytes().len() - 1).0.parse::<u64>().ok()?;
+            let (digits, suffix) = rem.split_at(rem.find(|c: char| !c.is_ascii_digit()).unwrap_or(rem.len()));
+            let age = digits.parse::<u64>().map_err(|_| (at(rem), "number".to_owned()))?;
+            let unit = match suffix.chars().next() {
+                Some(c) if suffix.len() == 1 => to_unit(c),
+                _ => None,
+            };
+            let unit = unit.ok_or((at(suffix), "time unit s, m, h or d".to_owned()))?;
 
-            Some(MetaDataFilter::Age(ord, age, unit))
-        } else if pattern.starts_with("type=") {
-            let mut rem = pattern["type=".len()..].chars();
-            let dir = match rem.next()? {
-                'd' => true,
-                'r' => false,
-                _ => return None,
+            Ok(MetaDataFilter::Age(cmp, age, unit))
+        } else if pattern.starts_with("mtime") {
+            let (cmp, rem) = operator(pattern, &pattern["mtime".len()..])?;
+            let time = to_timestamp(rem).ok_or((at(rem), "date such as 2019-10-01 or RFC 3339 time".to_owned()))?;
+
+            Ok(MetaDataFilter::MTime(cmp, time))
+        } else if pattern.starts_with("perm") {
+            let rem = &pattern["perm".len()..];
+            let how = match rem.chars().next() {
+                Some('=') => PermMatch::Exact,
+                Some('-') => PermMatch::All,
+                Some('/') => PermMatch::Any,
+                _ => return Err((at(rem), "'=', '-' or '/'".to_owned())),
             };
-            if rem.next().is_none() {
-                Some(MetaDataFilter::Type(dir))
-            } else {
-                None
+            let rem = &rem[1..];
+            let mode = to_mode(rem).ok_or((at(rem), "octal or symbolic mode such as 0644 or o+w".to_owned()))?;
+
+            Ok(MetaDataFilter::Perm(how, mode))
+        } else if pattern.starts_with("uid") {
+            let (cmp, rem) = operator(pattern, &pattern["uid".len()..])?;
+            let uid = rem.parse().map_err(|_| (at(rem), "numeric uid".to_owned()))?;
+
+            Ok(MetaDataFilter::Uid(cmp, uid))
+        } else if pattern.starts_with("gid") {
+            let (cmp, rem) = operator(pattern, &pattern["gid".len()..])?;
+            let gid = rem.parse().map_err(|_| (at(rem), "numeric gid".to_owned()))?;
+
+            Ok(MetaDataFilter::Gid(cmp, gid))
+        } else if pattern.starts_with("user") {
+            let (cmp, rem) = operator(pattern, &pattern["user".len()..])?;
+            if rem.is_empty() {
+                return Err((at(rem), "user name".to_owned()));
+            }
+
+            Ok(MetaDataFilter::User(cmp, rem.to_owned()))
+        } else if pattern.starts_with("group") {
+            let (cmp, rem) = operator(pattern, &pattern["group".len()..])?;
+            if rem.is_empty() {
+                return Err((at(rem), "group name".to_owned()));
+            }
+
+            Ok(MetaDataFilter::Group(cmp, rem.to_owned()))
+        } else if pattern.starts_with("name~") {
+            let rem = &pattern["name~".len()..];
+            if rem.starts_with('/') {
+                match literal_end(rem) {
+                    None => return Err((pattern.len(), "closing '/'".to_owned())),
+                    Some(end) if end + 1 < rem.len() => {
+                        return Err((at(rem) + end + 1, "'&', '|' or end of filter".to_owned()))
+                    }
+                    Some(_) => {}
+                }
             }
+            MetaDataFilter::name_regex(rem).map_err(|e| {
+                // the last line of a regex syntax error tells what went wrong
+                let reason = e.to_string();
+                let reason = reason.lines().last().unwrap_or("").trim_start_matches("error: ");
+                (at(rem), format!("regular expression ({})", reason))
+            })
+        } else if pattern.starts_with("name=") {
+            let rem = &pattern["name=".len()..];
+            let glob = Pattern::new(rem).map_err(|e| {
+                // glob counts characters, the offset is in bytes
+                let pos = rem.char_indices().nth(e.pos).map_or(rem.len(), |(i, _)| i);
+                (at(rem) + pos, format!("glob pattern ({})", e.msg))
+            })?;
+
+            Ok(MetaDataFilter::Name(glob))
+        } else if pattern.starts_with("type=") {
+            let rem = &pattern["type=".len()..];
+            match rem {
+                "d" => Ok(MetaDataFilter::Type(true)),
+                "r" => Ok(MetaDataFilter::Type(false)),
+                _ => Err((at(rem), "'d' or 'r'".to_owned())),
+            }
+        } else if pattern.is_empty() {
+            Err((0, "predicate".to_owned()))
         } else {
-            None
+            Err((0, "predicate on size, age, mtime, type, name, perm, uid, gid, user or group".to_owned()))
         }
     }
 
+    // User and group names mean nothing until they are looked up on the
+    // machine holding the files, so this is done there before checking.
+    pub fn resolve(self) -> Result<Self, String> {
+        use MetaDataFilter::*;
+        Ok(match self {
+            User(cmp, name) => match users::get_user_by_name(&name) {
+                Some(user) => Uid(cmp, user.uid()),
+                None => return Err(format!("no such user {:?}", name)),
+            },
+            Group(cmp, name) => match users::get_group_by_name(&name) {
+                Some(group) => Gid(cmp, group.gid()),
+                None => return Err(format!("no such group {:?}", name)),
+            },
+            And(lhs, rhs) => And(Box::new(lhs.resolve()?), Box::new(rhs.resolve()?)),
+            Or(lhs, rhs) => Or(Box::new(lhs.resolve()?), Box::new(rhs.resolve()?)),
+            Not(inner) => Not(Box::new(inner.resolve()?)),
+            filter => filter,
+        })
+    }
+
     #[inline]
     pub fn is_valid_pattern(pattern: &str) -> bool {
-        Self::new(pattern).is_some()
+        Self::new(pattern).is_ok()
+    }
+
+    // Whether `token` is meant as a filter rather than a path, even if it is
+    // malformed, so that the reason can be reported instead of "not found".
+    pub fn is_pattern_like(token: &str) -> bool {
+        // keyword and the characters an operator after it may start with
+        const KEYWORDS: [(&str, &str); 10] = [
+            ("size", "=<>!"),
+            ("age", "=<>!"),
+            ("mtime", "=<>!"),
+            ("type", "="),
+            ("name", "=~"),
+            ("perm", "=-/"),
+            ("uid", "=<>!"),
+            ("gid", "=<>!"),
+            ("user", "=<>!"),
+            ("group", "=<>!"),
+        ];
+        let token = token.trim_start_matches(|c: char| c == '(' || c == '!');
+        KEYWORDS
+            .iter()
+            .any(|(kw, ops)| token.starts_with(kw) && token[kw.len()..].starts_with(|c: char| ops.contains(c)))
     }
 
     #[inline]
-    pub fn check(&self, m: &Metadata) -> bool {
+    pub fn check(&self, name: &str, m: &Metadata) -> bool {
         match self {
             &MetaDataFilter::Empty => true,
-            _ => self.check_nonempty(m),
+            _ => self.check_nonempty(name, m),
         }
     }
 
-    fn check_nonempty(&self, m: &Metadata) -> bool {
+    fn check_nonempty(&self, name: &str, m: &Metadata) -> bool {
         use MetaDataFilter::*;
         match self {
-            &Size(ord, size) => m.len().cmp(&size) == ord,
-            &Age(ord, age, unit) => match m.modified() {
+            &Size(cmp, size) => cmp.test(m.len(), size),
+            &Age(cmp, age, unit) => match m.modified() {
                 Ok(time) => match time.elapsed() {
                     Ok(time) => {
                         let time = time.as_secs();
@@ -102,14 +532,201 @@ impl MetaDataFilter {
                             TimeUnit::Hour => time / 3600,
                             TimeUnit::Day => time / (3600 * 24),
                         };
-                        real_age.cmp(&age) == ord
+                        cmp.test(real_age, age)
                     }
                     Err(_) => true, // happening in the future
                 },
                 Err(_) => false, // unsupported platform
             },
+            &MTime(cmp, time) => match m.modified() {
+                Ok(mtime) => {
+                    let mtime = match mtime.duration_since(UNIX_EPOCH) {
+                        Ok(since) => since.as_secs() as i64,
+                        Err(e) => -(e.duration().as_secs() as i64), // before epoch
+                    };
+                    cmp.test(mtime, time)
+                }
+                Err(_) => false, // unsupported platform
+            },
             &Type(t) => t == m.is_dir(), // t true means dir
+            &Perm(how, mode) => {
+                let bits = m.mode() & 0o7777;
+                match how {
+                    PermMatch::Exact => bits == mode,
+                    PermMatch::All => bits & mode == mode,
+                    PermMatch::Any => bits & mode != 0,
+                }
+            }
+            &Uid(cmp, uid) => cmp.test(m.uid(), uid),
+            &Gid(cmp, gid) => cmp.test(m.gid(), gid),
+            &User(..) | &Group(..) => false, // not resolved
+            &Name(ref glob) => glob.matches(name),
+            &NameRegex(ref re) => re.is_match(name),
+            &And(ref lhs, ref rhs) => lhs.check(name, m) && rhs.check(name, m),
+            &Or(ref lhs, ref rhs) => lhs.check(name, m) || rhs.check(name, m),
+            &Not(ref inner) => !inner.check(name, m),
             &Empty => true,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the shape of a parsed filter, with names standing for their predicates
+    fn show(filter: &MetaDataFilter) -> String {
+        use MetaDataFilter::*;
+        match filter {
+            Empty => "*".to_owned(),
+            Type(true) => "d".to_owned(),
+            Type(false) => "r".to_owned(),
+            Name(glob) => glob.as_str().to_owned(),
+            NameRegex(re) => format!("~{}", re.as_str()),
+            Size(_, size) => format!("size{}", size),
+            And(lhs, rhs) => format!("({} & {})", show(lhs), show(rhs)),
+            Or(lhs, rhs) => format!("({} | {})", show(lhs), show(rhs)),
+            Not(inner) => format!("!{}", show(inner)),
+            _ => "?".to_owned(),
+        }
+    }
+
+    fn parse(pattern: &str) -> String {
+        match MetaDataFilter::new(pattern) {
+            Ok(filter) => show(&filter),
+            Err(e) => panic!("{:?} rejected: {}", pattern, e),
+        }
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        assert_eq!(parse("name=a|name=b&name=c"), "(a | (b & c))");
+        assert_eq!(parse("name=a&name=b|name=c"), "((a & b) | c)");
+        assert_eq!(parse("name=a&name=b&name=c"), "((a & b) & c)");
+        assert_eq!(parse("name=a|name=b|name=c"), "((a | b) | c)");
+    }
+
+    #[test]
+    fn parentheses_and_not() {
+        assert_eq!(parse("(name=a|name=b)&name=c"), "((a | b) & c)");
+        assert_eq!(parse("!(type=d&!(name=a|name=b))"), "!(d & !(a | b))");
+        assert_eq!(parse("!!type=d"), "!!d");
+        assert_eq!(parse("((type=r))"), "r");
+        assert_eq!(parse(" ( name=a | type=d ) & ! size>10 "), "((a | d) & !size10)");
+    }
+
+    #[test]
+    fn unmatched_parentheses() {
+        for pattern in ["(type=d", "type=d)", "((type=d)", "(type=d))", "()", ")"].iter() {
+            assert!(MetaDataFilter::new(pattern).is_err(), "{:?} accepted", pattern);
+        }
+    }
+
+    #[test]
+    fn empty_and_whitespace_only() {
+        assert_eq!(parse(""), "*");
+        assert_eq!(parse("   "), "*");
+        assert_eq!(parse("\t"), "*");
+    }
+
+    #[test]
+    fn incomplete_expressions() {
+        for pattern in ["type=d&", "|type=d", "type=d||type=r", "!", "type=d name=a"].iter() {
+            assert!(MetaDataFilter::new(pattern).is_err(), "{:?} accepted", pattern);
+        }
+    }
+
+    #[test]
+    fn bare_regex_keeps_its_groups() {
+        let filter = MetaDataFilter::new(r"name~app-\d{8}\.log(\.gz)?").unwrap();
+        assert_eq!(show(&filter), r"~app-\d{8}\.log(\.gz)?");
+        if let MetaDataFilter::NameRegex(re) = &filter {
+            assert!(re.is_match("app-20240101.log.gz"));
+            assert!(re.is_match("app-20240101.log"));
+            assert!(!re.is_match("app-2024.log"));
+        }
+
+        assert_eq!(parse("name~(a|b)c&type=r"), "(~(a|b)c & r)");
+        assert_eq!(parse("(name~x(y)|type=d)"), "(~x(y) | d)");
+        assert_eq!(parse(r"name~a\)|type=d"), r"(~a\) | d)");
+        assert_eq!(parse("name~[)|]&type=r"), "(~[)|] & r)");
+    }
+
+    fn error(pattern: &str) -> FilterError {
+        match MetaDataFilter::new(pattern) {
+            Ok(filter) => panic!("{:?} accepted as {}", pattern, show(&filter)),
+            Err(e) => e,
+        }
+    }
+
+    #[test]
+    fn unknown_key() {
+        let e = error("type=d&colour=red");
+        assert_eq!(e.pos, 7);
+        assert!(e.expected.starts_with("predicate on size"), "{}", e.expected);
+        assert_eq!(e.found, "colour=red");
+    }
+
+    #[test]
+    fn missing_value() {
+        let e = error("size>");
+        assert_eq!((e.pos, e.found.as_str()), (5, ""));
+        assert!(e.expected.starts_with("size such as"), "{}", e.expected);
+
+        let e = error("user=|type=d");
+        assert_eq!((e.pos, e.expected.as_str()), (5, "user name"));
+
+        let e = error("size");
+        assert_eq!((e.pos, e.expected.as_str()), (4, "comparison operator"));
+    }
+
+    #[test]
+    fn bad_unit() {
+        let e = error("age<2x");
+        assert_eq!((e.pos, e.expected.as_str(), e.found.as_str()), (5, "time unit s, m, h or d", "x"));
+
+        let e = error("size>10X");
+        assert_eq!(e.pos, 5);
+        assert!(e.expected.starts_with("size such as"), "{}", e.expected);
+    }
+
+    #[test]
+    fn unmatched_close_paren() {
+        let e = error("type=d)");
+        assert_eq!((e.pos, e.expected.as_str(), e.found.as_str()), (6, "'&', '|' or end of filter", ")"));
+
+        let e = error("(type=d");
+        assert_eq!((e.pos, e.expected.as_str(), e.found.as_str()), (7, "')'", ""));
+    }
+
+    #[test]
+    fn unterminated_regex_literal() {
+        let e = error("name~/abc");
+        assert_eq!((e.pos, e.expected.as_str()), (9, "closing '/'"));
+
+        let e = error("name~/a\\/");
+        assert_eq!((e.pos, e.expected.as_str()), (9, "closing '/'"));
+
+        let e = error("name~/a/b");
+        assert_eq!((e.pos, e.expected.as_str()), (8, "'&', '|' or end of filter"));
+    }
+
+    #[test]
+    fn position_counts_characters() {
+        // é is two bytes but one position
+        let e = error("name=é&size>x");
+        assert_eq!(e.pos, 12);
+    }
+
+    #[test]
+    fn glob_position_counts_characters() {
+        assert_eq!(error("name=é[").pos, 6);
+        assert_eq!(error("name=éé[!").pos, 7);
+    }
+
+    #[test]
+    fn regex_literal_may_contain_operators() {
+        assert_eq!(parse("name~/a|(b)/&type=r"), "(~a|(b) & r)");
+        assert_eq!(parse("name~/a\\/b/"), "~a/b");
+    }
+}
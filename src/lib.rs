@@ -1,10 +1,14 @@
+pub mod checksum;
 pub mod error;
 pub mod filter;
 pub mod protos;
+pub mod sffsclient;
 pub mod sffsserver;
+pub mod walk;
 pub mod common;
 
 pub use error::{CommonErrorKind, ExecuteError, Result};
+pub use sffsclient::SFFSClient;
 pub use sffsserver::SFFSServer;
 
 #[cfg(feature = "jemalloc")]
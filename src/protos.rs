@@ -55,9 +55,16 @@ impl From<String> for sffs::ListOption {
     }
 }
 
+use crate::filter::{self, MetaDataFilter};
+use glob::Pattern;
+use nix::sys::statvfs;
+use protobuf::SingularPtrField;
+use regex::Regex;
+
 use std::convert::{TryFrom, TryInto};
 use std::fs;
 use std::io;
+use std::os::unix::fs::MetadataExt;
 use std::time::SystemTime;
 
 impl TryFrom<(String, fs::Metadata)> for sffs::DirEntry {
@@ -72,6 +79,7 @@ impl TryFrom<(String, fs::Metadata)> for sffs::DirEntry {
             isdir: meta.is_dir(),
             size: meta.len() as i64,
             modifytime: mtime.as_secs() as i64,
+            nlink: meta.nlink() as i64,
             ..Default::default()
         })
     }
@@ -118,3 +126,343 @@ impl From<(i64, i64)> for sffs::Range {
         }
     }
 }
+
+impl From<(&str, &str)> for sffs::Xattr {
+    #[inline]
+    fn from((path, name): (&str, &str)) -> Self {
+        Self {
+            path: path.to_owned(),
+            name: name.to_owned(),
+            ..Default::default()
+        }
+    }
+}
+
+impl From<(&str, &str, Vec<u8>)> for sffs::Xattr {
+    #[inline]
+    fn from((path, name, value): (&str, &str, Vec<u8>)) -> Self {
+        Self {
+            path: path.to_owned(),
+            name: name.to_owned(),
+            value,
+            ..Default::default()
+        }
+    }
+}
+
+impl From<Option<Vec<u8>>> for sffs::XattrValue {
+    #[inline]
+    fn from(v: Option<Vec<u8>>) -> Self {
+        Self {
+            found: v.is_some(),
+            value: v.unwrap_or_default(),
+            ..Default::default()
+        }
+    }
+}
+
+impl From<Vec<String>> for sffs::XattrList {
+    #[inline]
+    fn from(names: Vec<String>) -> Self {
+        Self {
+            names: names.into(),
+            ..Default::default()
+        }
+    }
+}
+
+impl From<(&str, &str)> for sffs::Link {
+    #[inline]
+    fn from((source, target): (&str, &str)) -> Self {
+        Self {
+            source: source.to_owned(),
+            target: target.to_owned(),
+            ..Default::default()
+        }
+    }
+}
+
+impl From<Option<u64>> for sffs::LinkResult {
+    #[inline]
+    fn from(nlink: Option<u64>) -> Self {
+        Self {
+            success: nlink.is_some(),
+            nlink: nlink.unwrap_or(0) as i64,
+            ..Default::default()
+        }
+    }
+}
+
+impl From<statvfs::Statvfs> for sffs::FsStat {
+    fn from(st: statvfs::Statvfs) -> Self {
+        let frsize = st.fragment_size() as i64;
+        Self {
+            totalbytes: st.blocks() as i64 * frsize,
+            freebytes: st.blocks_free() as i64 * frsize,
+            availbytes: st.blocks_available() as i64 * frsize,
+            totalinodes: st.files() as i64,
+            freeinodes: st.files_free() as i64,
+            availinodes: st.files_available() as i64,
+            ..Default::default()
+        }
+    }
+}
+
+impl From<filter::Comparison> for sffs::Comparison {
+    fn from(cmp: filter::Comparison) -> Self {
+        use filter::Comparison::*;
+        match cmp {
+            Equal => sffs::Comparison::EQUAL,
+            NotEqual => sffs::Comparison::NOTEQUAL,
+            Less => sffs::Comparison::LESS,
+            LessEqual => sffs::Comparison::LESSEQUAL,
+            Greater => sffs::Comparison::GREATER,
+            GreaterEqual => sffs::Comparison::GREATEREQUAL,
+        }
+    }
+}
+
+impl From<sffs::Comparison> for filter::Comparison {
+    fn from(cmp: sffs::Comparison) -> Self {
+        use sffs::Comparison::*;
+        match cmp {
+            EQUAL => filter::Comparison::Equal,
+            NOTEQUAL => filter::Comparison::NotEqual,
+            LESS => filter::Comparison::Less,
+            LESSEQUAL => filter::Comparison::LessEqual,
+            GREATER => filter::Comparison::Greater,
+            GREATEREQUAL => filter::Comparison::GreaterEqual,
+        }
+    }
+}
+
+impl From<filter::TimeUnit> for sffs::TimeUnit {
+    fn from(unit: filter::TimeUnit) -> Self {
+        use filter::TimeUnit::*;
+        match unit {
+            Second => sffs::TimeUnit::SECOND,
+            Minute => sffs::TimeUnit::MINUTE,
+            Hour => sffs::TimeUnit::HOUR,
+            Day => sffs::TimeUnit::DAY,
+        }
+    }
+}
+
+impl From<sffs::TimeUnit> for filter::TimeUnit {
+    fn from(unit: sffs::TimeUnit) -> Self {
+        use sffs::TimeUnit::*;
+        match unit {
+            SECOND => filter::TimeUnit::Second,
+            MINUTE => filter::TimeUnit::Minute,
+            HOUR => filter::TimeUnit::Hour,
+            DAY => filter::TimeUnit::Day,
+        }
+    }
+}
+
+impl From<filter::PermMatch> for sffs::PermMatch {
+    fn from(how: filter::PermMatch) -> Self {
+        use filter::PermMatch::*;
+        match how {
+            Exact => sffs::PermMatch::EXACT,
+            All => sffs::PermMatch::ALL,
+            Any => sffs::PermMatch::ANY,
+        }
+    }
+}
+
+impl From<sffs::PermMatch> for filter::PermMatch {
+    fn from(how: sffs::PermMatch) -> Self {
+        use sffs::PermMatch::*;
+        match how {
+            EXACT => filter::PermMatch::Exact,
+            ALL => filter::PermMatch::All,
+            ANY => filter::PermMatch::Any,
+        }
+    }
+}
+
+impl From<&MetaDataFilter> for sffs::Filter {
+    fn from(f: &MetaDataFilter) -> Self {
+        use MetaDataFilter::*;
+
+        #[inline]
+        fn pair(lhs: &MetaDataFilter, rhs: &MetaDataFilter) -> sffs::FilterPair {
+            sffs::FilterPair {
+                lhs: SingularPtrField::some(lhs.into()),
+                rhs: SingularPtrField::some(rhs.into()),
+                ..Default::default()
+            }
+        }
+        #[inline]
+        fn id(cmp: filter::Comparison, id: u32) -> sffs::IdFilter {
+            sffs::IdFilter {
+                cmp: cmp.into(),
+                id,
+                ..Default::default()
+            }
+        }
+        #[inline]
+        fn name(cmp: filter::Comparison, name: &str) -> sffs::NameFilter {
+            sffs::NameFilter {
+                cmp: cmp.into(),
+                name: name.to_owned(),
+                ..Default::default()
+            }
+        }
+
+        let mut filter = Self::new();
+        match f {
+            &Empty => filter.set_all(true),
+            &Size(cmp, size) => filter.set_size(sffs::SizeFilter {
+                cmp: cmp.into(),
+                size,
+                ..Default::default()
+            }),
+            &Age(cmp, age, unit) => filter.set_age(sffs::AgeFilter {
+                cmp: cmp.into(),
+                age,
+                unit: unit.into(),
+                ..Default::default()
+            }),
+            &MTime(cmp, time) => filter.set_mtime(sffs::MTimeFilter {
+                cmp: cmp.into(),
+                time,
+                ..Default::default()
+            }),
+            &Type(isdir) => filter.set_isdir(isdir),
+            &Perm(how, mode) => filter.set_perm(sffs::PermFilter {
+                how: how.into(),
+                mode,
+                ..Default::default()
+            }),
+            &Uid(cmp, uid) => filter.set_uid(id(cmp, uid)),
+            &Gid(cmp, gid) => filter.set_gid(id(cmp, gid)),
+            &User(cmp, ref user) => filter.set_user(name(cmp, user)),
+            &Group(cmp, ref group) => filter.set_group(name(cmp, group)),
+            &Name(ref glob) => filter.set_glob(glob.as_str().to_owned()),
+            &NameRegex(ref re) => filter.set_regex(re.as_str().to_owned()),
+            &And(ref lhs, ref rhs) => filter.set_and(pair(lhs, rhs)),
+            &Or(ref lhs, ref rhs) => filter.set_or(pair(lhs, rhs)),
+            &Not(ref inner) => filter.set_not(sffs::FilterNot {
+                operand: SingularPtrField::some((&**inner).into()),
+                ..Default::default()
+            }),
+        }
+        filter
+    }
+}
+
+impl TryFrom<&sffs::Filter> for MetaDataFilter {
+    type Error = String;
+    fn try_from(filter: &sffs::Filter) -> Result<Self, Self::Error> {
+        use sffs::Filter_oneof_kind as Kind;
+        use MetaDataFilter::*;
+
+        Ok(match filter.kind {
+            Some(Kind::all(_)) => Empty,
+            Some(Kind::size(ref f)) => Size(f.cmp.into(), f.size),
+            Some(Kind::age(ref f)) => Age(f.cmp.into(), f.age, f.unit.into()),
+            Some(Kind::mtime(ref f)) => MTime(f.cmp.into(), f.time),
+            Some(Kind::isdir(isdir)) => Type(isdir),
+            Some(Kind::glob(ref glob)) => {
+                Name(Pattern::new(glob).map_err(|e| format!("invalid glob pattern {:?}: {}", glob, e.msg))?)
+            }
+            Some(Kind::regex(ref re)) => NameRegex(Regex::new(re).map_err(|e| e.to_string())?),
+            Some(Kind::perm(ref f)) => Perm(f.how.into(), f.mode),
+            Some(Kind::uid(ref f)) => Uid(f.cmp.into(), f.id),
+            Some(Kind::gid(ref f)) => Gid(f.cmp.into(), f.id),
+            Some(Kind::user(ref f)) => User(f.cmp.into(), f.name.clone()),
+            Some(Kind::group(ref f)) => Group(f.cmp.into(), f.name.clone()),
+            Some(Kind::and(ref f)) => And(
+                Box::new(MetaDataFilter::try_from(f.get_lhs())?),
+                Box::new(MetaDataFilter::try_from(f.get_rhs())?),
+            ),
+            Some(Kind::or(ref f)) => Or(
+                Box::new(MetaDataFilter::try_from(f.get_lhs())?),
+                Box::new(MetaDataFilter::try_from(f.get_rhs())?),
+            ),
+            Some(Kind::not(ref f)) => Not(Box::new(MetaDataFilter::try_from(f.get_operand())?)),
+            // set by a newer client with a predicate unknown here, or missing
+            None => return Err("unsupported filter, the client may be newer than the server".to_owned()),
+        })
+    }
+}
+
+// Only the typed filter is sent, the server rejects a request carrying both.
+impl From<&MetaDataFilter> for sffs::ListOption {
+    #[inline]
+    fn from(filter: &MetaDataFilter) -> Self {
+        Self {
+            filter: SingularPtrField::some(filter.into()),
+            ..Default::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use filter::{Comparison, PermMatch, TimeUnit};
+
+    // converting back and forth must give the same message again
+    fn round_trip(filter: MetaDataFilter) {
+        let message = sffs::Filter::from(&filter);
+        let back = MetaDataFilter::try_from(&message).unwrap();
+        assert_eq!(sffs::Filter::from(&back), message);
+    }
+
+    #[test]
+    fn round_trip_every_variant() {
+        use MetaDataFilter::*;
+        let comparisons = [
+            Comparison::Equal,
+            Comparison::NotEqual,
+            Comparison::Less,
+            Comparison::LessEqual,
+            Comparison::Greater,
+            Comparison::GreaterEqual,
+        ];
+        for &cmp in comparisons.iter() {
+            round_trip(Size(cmp, 10_000_000));
+            round_trip(MTime(cmp, -86400));
+            round_trip(Uid(cmp, 1000));
+            round_trip(Gid(cmp, 100));
+            round_trip(User(cmp, "root".to_owned()));
+            round_trip(Group(cmp, "wheel".to_owned()));
+        }
+        for &unit in [TimeUnit::Second, TimeUnit::Minute, TimeUnit::Hour, TimeUnit::Day].iter() {
+            round_trip(Age(Comparison::Less, 2, unit));
+        }
+        for &how in [PermMatch::Exact, PermMatch::All, PermMatch::Any].iter() {
+            round_trip(Perm(how, 0o4755));
+        }
+        round_trip(Empty);
+        round_trip(Type(true));
+        round_trip(Type(false));
+        round_trip(Name(Pattern::new("*.log").unwrap()));
+        round_trip(NameRegex(Regex::new(r"app-\d{8}\.log(\.gz)?").unwrap()));
+        round_trip(And(Box::new(Type(false)), Box::new(Not(Box::new(Or(Box::new(Empty), Box::new(Type(true))))))));
+    }
+
+    #[test]
+    fn round_trip_keeps_patterns() {
+        let message = sffs::Filter::from(&MetaDataFilter::Name(Pattern::new("[ab]*.gz").unwrap()));
+        match MetaDataFilter::try_from(&message).unwrap() {
+            MetaDataFilter::Name(glob) => assert_eq!(glob.as_str(), "[ab]*.gz"),
+            _ => panic!("not a glob"),
+        }
+        let message = sffs::Filter::from(&MetaDataFilter::NameRegex(Regex::new("^a|b$").unwrap()));
+        match MetaDataFilter::try_from(&message).unwrap() {
+            MetaDataFilter::NameRegex(re) => assert_eq!(re.as_str(), "^a|b$"),
+            _ => panic!("not a regex"),
+        }
+    }
+
+    #[test]
+    fn list_option_carries_no_string() {
+        let option = sffs::ListOption::from(&MetaDataFilter::Type(true));
+        assert!(option.get_option().is_empty());
+        assert!(option.has_filter());
+    }
+}
@@ -0,0 +1,330 @@
+use grpcio::{ChannelBuilder, EnvBuilder};
+
+use crate::common;
+use crate::error::{CommonErrorKind, Result};
+use crate::protos::{sffs, sffs_grpc::SffsClient, MAX_BLOCK_SIZE};
+
+use std::io::prelude::*;
+use std::sync::Arc;
+
+#[derive(Clone, Copy)]
+enum RemoteKind {
+    File,
+    List,
+    Find,
+}
+
+// The server keeps one open file, list and find per connection, so at most
+// one handle of each kind should be alive at a time.
+pub struct RemoteFile<'a> {
+    client: Option<&'a SffsClient>,
+    kind: RemoteKind,
+}
+
+impl<'a> RemoteFile<'a> {
+    fn _new(client: &'a SffsClient, name: &str, kind: RemoteKind, is_found: bool) -> Result<Self> {
+        if is_found {
+            Ok(Self {
+                client: Some(client),
+                kind,
+            })
+        } else {
+            Err(CommonErrorKind::NotFound(name.to_owned()).into())
+        }
+    }
+
+    #[inline]
+    fn client(&self) -> Result<&'a SffsClient> {
+        self.client.ok_or_else(|| CommonErrorKind::CloseFail.into())
+    }
+
+    // next block of the file, empty at the end
+    pub fn read(&mut self) -> Result<Vec<u8>> {
+        Ok(self.client()?.nextread(&sffs::Void::new())?.take_data())
+    }
+
+    // at most MAX_BLOCK_SIZE bytes starting from `start`
+    pub fn read_at(&mut self, start: u64, count: usize) -> Result<Vec<u8>> {
+        if count > MAX_BLOCK_SIZE {
+            return Err(CommonErrorKind::InvalidArgument.into());
+        }
+        let range = (start as i64, count as i64).into();
+        Ok(self.client()?.randomread(&range)?.take_data())
+    }
+
+    // `data` must not be longer than MAX_BLOCK_SIZE
+    pub fn write(&mut self, data: Vec<u8>) -> Result<()> {
+        if self.client()?.nextwrite(&data.into())?.get_value() {
+            Ok(())
+        } else {
+            Err("cannot write remote file".into())
+        }
+    }
+
+    pub fn close(&mut self) -> Result<()> {
+        let client = match self.client.take() {
+            Some(client) => client,
+            None => return Ok(()),
+        };
+        let reply = match self.kind {
+            RemoteKind::File => client.closefile(&sffs::Void::new())?,
+            RemoteKind::List => client.closelist(&sffs::Void::new())?,
+            RemoteKind::Find => client.closefind(&sffs::Void::new())?,
+        };
+        if reply.get_value() {
+            Ok(())
+        } else {
+            Err(CommonErrorKind::CloseFail.into())
+        }
+    }
+}
+
+impl<'a> Drop for RemoteFile<'a> {
+    fn drop(&mut self) {
+        // left open after an early return, the server may still hold it
+        if let Err(e) = self.close() {
+            eprintln!("{}", e);
+        }
+    }
+}
+
+// Entries of an open list or find, fetched one by one.
+pub struct RemoteList<'a> {
+    handle: RemoteFile<'a>,
+}
+
+impl<'a> RemoteList<'a> {
+    pub fn close(&mut self) -> Result<()> {
+        self.handle.close()
+    }
+}
+
+impl<'a> Iterator for RemoteList<'a> {
+    type Item = Result<sffs::DirEntry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let client = self.handle.client?;
+        let reply = match self.handle.kind {
+            RemoteKind::Find => client.nextfind(&sffs::Void::new()),
+            _ => client.nextlist(&sffs::Void::new()),
+        };
+        match reply {
+            Ok(ref entry) if entry.get_name().is_empty() => match self.handle.close() {
+                Ok(()) => None,
+                Err(e) => Some(Err(e)),
+            },
+            Ok(entry) => Some(Ok(entry)),
+            Err(e) => Some(Err(e.into())),
+        }
+    }
+}
+
+pub struct SFFSClient {
+    client: SffsClient,
+}
+
+impl SFFSClient {
+    pub fn connect(host: &str) -> Self {
+        let addr = format!("{}:{}", host, common::COMM_PORT);
+        let env = Arc::new(EnvBuilder::new().build());
+        let ch = ChannelBuilder::new(env).connect(&addr);
+        Self {
+            client: SffsClient::new(ch),
+        }
+    }
+
+    pub fn getdir(&self) -> Result<String> {
+        Ok(self.client.getdir(&sffs::Void::new())?.take_value())
+    }
+
+    pub fn cd(&self, dir: &str) -> Result<()> {
+        if self.client.changedir(&dir.into())?.get_value() {
+            Ok(())
+        } else {
+            Err(CommonErrorKind::NotFound(dir.to_owned()).into())
+        }
+    }
+
+    // succeeds if the directory already exists
+    pub fn mkdir(&self, dir: &str) -> Result<()> {
+        if self.client.makedir(&dir.into())?.get_value() {
+            Ok(())
+        } else {
+            Err(format!("cannot create remote directory {}", dir).into())
+        }
+    }
+
+    pub fn stat(&self, path: &str) -> Result<sffs::DirEntry> {
+        let entry = self.client.stat(&path.into())?;
+        if entry.get_name().is_empty() {
+            Err(CommonErrorKind::NotFound(path.to_owned()).into())
+        } else {
+            Ok(entry)
+        }
+    }
+
+    pub fn filecount(&self, req: &sffs::CountRequest) -> Result<sffs::FileCount> {
+        Ok(self.client.filecount(req)?)
+    }
+
+    pub fn list(&self, dir: &str, option: Option<sffs::ListOption>, sort: sffs::SortOption) -> Result<RemoteList> {
+        let mut request = sffs::ListRequest::new();
+        request.set_dir(dir.to_owned());
+        request.set_sort(sort);
+        if let Some(option) = option {
+            request.set_option(option);
+        }
+
+        let is_found = self.client.openlist(&request)?;
+        let handle = RemoteFile::_new(&self.client, dir, RemoteKind::List, is_found.get_value())?;
+        Ok(RemoteList { handle })
+    }
+
+    pub fn listpage(&self, req: &sffs::ListRequest) -> Result<sffs::DirPage> {
+        Ok(self.client.listpage(req)?)
+    }
+
+    // entry names are paths relative to `dir`, maxdepth 0 means unlimited
+    pub fn find(&self, dir: &str, option: Option<sffs::ListOption>, maxdepth: i32, follow: bool) -> Result<RemoteList> {
+        let mut request = sffs::FindRequest::new();
+        request.set_dir(dir.to_owned());
+        request.set_maxdepth(maxdepth);
+        request.set_follow(follow);
+        if let Some(option) = option {
+            request.set_option(option);
+        }
+
+        let is_found = self.client.openfind(&request)?;
+        let handle = RemoteFile::_new(&self.client, dir, RemoteKind::Find, is_found.get_value())?;
+        Ok(RemoteList { handle })
+    }
+
+    pub fn open(&self, name: &str) -> Result<RemoteFile> {
+        let is_found = self.client.openfiletoread(&name.into())?;
+        RemoteFile::_new(&self.client, name, RemoteKind::File, is_found.get_value())
+    }
+
+    pub fn create(&self, name: &str) -> Result<RemoteFile> {
+        let is_found = self.client.openfiletowrite(&name.into())?;
+        RemoteFile::_new(&self.client, name, RemoteKind::File, is_found.get_value())
+    }
+
+    // copies the remote file into `writer`, returning the number of bytes
+    pub fn get<W: Write + ?Sized>(&self, remotepath: &str, writer: &mut W) -> Result<u64> {
+        self.get_with_progress(remotepath, writer, |_| ())
+    }
+
+    // as get, calling `progress` with the bytes so far after each block
+    pub fn get_with_progress<W, F>(&self, remotepath: &str, writer: &mut W, progress: F) -> Result<u64>
+    where
+        W: Write + ?Sized,
+        F: FnMut(u64),
+    {
+        let remotefile = self.open(remotepath)?;
+        self.get_opened(remotefile, writer, progress)
+    }
+
+    // as get_with_progress, for a file the caller has already opened so that
+    // it can check the remote side before touching anything local
+    pub fn get_opened<W, F>(&self, mut remotefile: RemoteFile, writer: &mut W, mut progress: F) -> Result<u64>
+    where
+        W: Write + ?Sized,
+        F: FnMut(u64),
+    {
+        let mut bytes = 0;
+        loop {
+            let data = remotefile.read()?;
+            if data.is_empty() {
+                break;
+            }
+            writer.write_all(&data)?;
+            bytes += data.len() as u64;
+            progress(bytes);
+        }
+
+        remotefile.close()?;
+        Ok(bytes)
+    }
+
+    // copies everything from `reader` into the remote file, returning the number of bytes
+    pub fn put<R: Read + ?Sized>(&self, reader: &mut R, remotepath: &str) -> Result<u64> {
+        self.put_with_progress(reader, remotepath, |_| ())
+    }
+
+    // as put, calling `progress` with the bytes so far after each block
+    pub fn put_with_progress<R, F>(&self, reader: &mut R, remotepath: &str, mut progress: F) -> Result<u64>
+    where
+        R: Read + ?Sized,
+        F: FnMut(u64),
+    {
+        let mut remotefile = self.create(remotepath)?;
+
+        let mut bytes = 0;
+        loop {
+            let mut buf = vec![0u8; MAX_BLOCK_SIZE];
+
+            let len = reader.read(&mut buf)?;
+            if len == 0 {
+                break;
+            }
+            buf.truncate(len);
+
+            remotefile.write(buf)?;
+            bytes += len as u64;
+            progress(bytes);
+        }
+
+        remotefile.close()?;
+        Ok(bytes)
+    }
+
+    pub fn randomread(&self, remotepath: &str, start: u64, count: usize) -> Result<Vec<u8>> {
+        let mut remotefile = self.open(remotepath)?;
+        let data = remotefile.read_at(start, count)?;
+        remotefile.close()?;
+        Ok(data)
+    }
+
+    pub fn listxattr(&self, path: &str) -> Result<Vec<String>> {
+        Ok(self.client.listxattr(&path.into())?.take_names().into_vec())
+    }
+
+    pub fn getxattr(&self, path: &str, name: &str) -> Result<Option<Vec<u8>>> {
+        let mut reply = self.client.getxattr(&(path, name).into())?;
+        if reply.get_found() {
+            Ok(Some(reply.take_value()))
+        } else {
+            Ok(None)
+        }
+    }
+
+    pub fn setxattr(&self, path: &str, name: &str, value: Vec<u8>) -> Result<bool> {
+        Ok(self.client.setxattr(&(path, name, value).into())?.get_value())
+    }
+
+    pub fn removexattr(&self, path: &str, name: &str) -> Result<bool> {
+        Ok(self.client.removexattr(&(path, name).into())?.get_value())
+    }
+
+    // the link count of the target on success
+    pub fn link(&self, source: &str, target: &str) -> Result<Option<u64>> {
+        let reply = self.client.link(&(source, target).into())?;
+        if reply.get_success() {
+            Ok(Some(reply.get_nlink() as u64))
+        } else {
+            Ok(None)
+        }
+    }
+
+    pub fn statfs(&self, path: &str) -> Result<sffs::FsStat> {
+        Ok(self.client.statfs(&path.into())?)
+    }
+
+    pub fn diskusage(&self, req: &sffs::DiskUsageRequest) -> Result<sffs::DiskUsage> {
+        Ok(self.client.diskusage(req)?)
+    }
+
+    pub fn checksum(&self, req: &sffs::ChecksumRequest) -> Result<sffs::Checksum> {
+        Ok(self.client.checksum(req)?)
+    }
+}
@@ -1,28 +1,30 @@
 use futures::Future;
 use grpcio::{RpcContext, RpcStatus, RpcStatusCode, UnarySink};
+use nix::sys::statvfs;
 use nix::unistd;
+use protobuf::Message;
 
+use crate::checksum;
 use crate::filter::MetaDataFilter;
 use crate::protos::{sffs, sffs_grpc::Sffs, MAX_BLOCK_SIZE};
+use crate::walk::Walk;
 
+use std::cmp::Ordering;
+use std::collections::HashSet;
 use std::convert::{TryFrom, TryInto};
 use std::env;
-use std::fs::{self, File, OpenOptions, ReadDir};
+use std::fs::{self, File, Metadata, OpenOptions};
 use std::io::prelude::*;
-use std::os::unix::prelude::FileExt;
-use std::path::PathBuf;
+use std::os::unix::prelude::{FileExt, MetadataExt};
+use std::path::Path;
 use std::sync::{Arc, Mutex};
-
-#[derive(Clone, Copy)]
-enum NextEntry {
-    Dot,    // "."
-    DotDot, // ".."
-    File,   // any file
-}
+use std::vec;
 
 #[derive(Default)]
 struct SFFSServerInner {
-    opendir: Mutex<Option<(ReadDir, PathBuf, NextEntry, MetaDataFilter)>>,
+    // entries are read when the list is opened so that they can be sorted
+    opendir: Mutex<Option<vec::IntoIter<sffs::DirEntry>>>,
+    openfind: Mutex<Option<(Walk, MetaDataFilter)>>,
     openfile: Mutex<Option<File>>,
 }
 
@@ -35,6 +37,153 @@ impl SFFSServer {
     }
 }
 
+// ".", ".." and then the directory entries, skipping those that cannot be read
+fn read_entries(dir: &Path, filter: &MetaDataFilter) -> Option<Vec<sffs::DirEntry>> {
+    let files = fs::read_dir(dir).ok()?;
+
+    let mut entries: Vec<sffs::DirEntry> = Vec::new();
+    for &ename in [".", ".."].iter() {
+        let meta = match File::open(dir.join(ename)).and_then(|f| f.metadata()) {
+            Ok(meta) => meta,
+            Err(_) => continue,
+        };
+        if filter.check(ename, &meta) {
+            if let Ok(entry) = (ename.to_owned(), meta).try_into() {
+                entries.push(entry);
+            }
+        }
+    }
+    for entry in files.filter_map(|e| e.ok()) {
+        let meta = match entry.metadata() {
+            Ok(meta) => meta,
+            Err(_) => continue,
+        };
+        if filter.check(&entry.file_name().to_string_lossy(), &meta) {
+            if let Ok(entry) = sffs::DirEntry::try_from(entry) {
+                entries.push(entry);
+            }
+        }
+    }
+    Some(entries)
+}
+
+fn compare_entries(a: &sffs::DirEntry, b: &sffs::DirEntry, sort: &sffs::SortOption) -> Ordering {
+    use sffs::SortKey::*;
+    let key = sort.get_key();
+    let ord = match key {
+        NONE => Ordering::Equal, // keep ReadDir order
+        NAME => a.get_name().cmp(b.get_name()),
+        SIZE => a.get_size().cmp(&b.get_size()),
+        MTIME => a.get_modifytime().cmp(&b.get_modifytime()),
+        TYPE => b.get_isdir().cmp(&a.get_isdir()),
+    };
+    // ties are broken by name so that the order is stable across calls
+    let ord = match key {
+        NONE => ord,
+        _ => ord.then_with(|| a.get_name().cmp(b.get_name())),
+    };
+    let ord = if sort.get_reverse() { ord.reverse() } else { ord };
+    if sort.get_dirsfirst() {
+        b.get_isdir().cmp(&a.get_isdir()).then(ord)
+    } else {
+        ord
+    }
+}
+
+#[inline]
+fn sort_entries(entries: &mut [sffs::DirEntry], sort: &sffs::SortOption) {
+    entries.sort_by(|a, b| compare_entries(a, b, sort));
+}
+
+// Pages are cut from separate listings, so their order must be the same
+// every time. ReadDir order is not stable, NONE sorts by name instead.
+fn page_sort(sort: &sffs::SortOption) -> sffs::SortOption {
+    let mut sort = sort.clone();
+    if sort.get_key() == sffs::SortKey::NONE {
+        sort.set_key(sffs::SortKey::NAME);
+    }
+    sort
+}
+
+// The page of `entries`, sorted by `sort`, that follows `cursor`. The cursor
+// is the last entry of the previous page, the next one starts after where it
+// sorts, so it still works when that entry has been deleted meanwhile.
+fn page_entries(
+    mut entries: Vec<sffs::DirEntry>,
+    sort: &sffs::SortOption,
+    cursor: &[u8],
+    limit: i64,
+) -> Option<sffs::DirPage> {
+    let start = if cursor.is_empty() {
+        0
+    } else {
+        let last = protobuf::parse_from_bytes::<sffs::DirEntry>(cursor).ok()?;
+        (entries.iter())
+            .position(|e| compare_entries(e, &last, sort) == Ordering::Greater)
+            .unwrap_or(entries.len())
+    };
+    let end = match limit {
+        limit if limit > 0 => entries.len().min(start + limit as usize),
+        _ => entries.len(),
+    };
+
+    let mut page = sffs::DirPage::new();
+    if end < entries.len() {
+        page.set_cursor(entries[end - 1].write_to_bytes().ok()?);
+    }
+    page.set_entries(entries.drain(start..end).collect::<Vec<_>>().into());
+    Some(page)
+}
+
+fn add_stats(stats: &mut sffs::EntryStats, entry: &sffs::DirEntry) {
+    let first = stats.count == 0;
+    if first || entry.size > stats.largestsize {
+        stats.largest = entry.name.clone();
+        stats.largestsize = entry.size;
+    }
+    if first || entry.modifytime < stats.oldest {
+        stats.oldest = entry.modifytime;
+    }
+    if first || entry.modifytime > stats.newest {
+        stats.newest = entry.modifytime;
+    }
+    stats.count += 1;
+    stats.bytes += entry.size;
+}
+
+// Add up `path` and everything below it. Directories are counted but only
+// non-directory sizes are summed. Symlinks are never followed. A file with
+// several hard links is counted at the first one found, `seen` holds those.
+fn accumulate(
+    path: &Path,
+    meta: &Metadata,
+    filter: &MetaDataFilter,
+    seen: &mut HashSet<(u64, u64)>,
+    usage: &mut sffs::DiskUsage,
+) {
+    let name = path.file_name().map(|name| name.to_string_lossy()).unwrap_or_default();
+    if filter.check(&name, meta) {
+        if meta.is_dir() {
+            usage.dirs += 1;
+        } else if meta.nlink() <= 1 || seen.insert((meta.dev(), meta.ino())) {
+            usage.files += 1;
+            usage.bytes += meta.len() as i64;
+            usage.used += (meta.blocks() * 512) as i64;
+        }
+    }
+    if meta.is_dir() {
+        let entries = match fs::read_dir(path) {
+            Ok(entries) => entries,
+            Err(_) => return, // unreadable, skip the subtree
+        };
+        for entry in entries.filter_map(|e| e.ok()) {
+            if let Ok(meta) = entry.metadata() {
+                accumulate(&entry.path(), &meta, filter, seen, usage);
+            }
+        }
+    }
+}
+
 macro_rules! to_future {
     ($sink:expr, $res:expr) => {
         match $res {
@@ -50,6 +199,34 @@ macro_rules! reply {
     };
 }
 
+// The string is from older clients, newer ones only send the typed filter.
+fn request_filter(option: &str, filter: Option<&sffs::Filter>) -> Result<MetaDataFilter, String> {
+    let filter = match filter {
+        Some(_) if !option.is_empty() => return Err("a filter is either a string or typed, not both".to_owned()),
+        Some(filter) => MetaDataFilter::try_from(filter)?,
+        None => MetaDataFilter::new(option).map_err(|e| e.to_string())?,
+    };
+    filter.resolve()
+}
+
+// Get the filter of a request, replying InvalidArgument with the reason if
+// it is malformed.
+macro_rules! parse_filter {
+    ($ctx:expr, $req:expr, $sink:expr, $option:expr, $filter:expr) => {
+        match request_filter($option, $filter) {
+            Ok(filter) => filter,
+            Err(reason) => {
+                let status = RpcStatus::new(RpcStatusCode::InvalidArgument, Some(reason));
+                reply!($ctx, $req, $sink.fail(status));
+                return;
+            }
+        }
+    };
+    ($ctx:expr, $req:expr, $sink:expr, $option:expr) => {
+        parse_filter!($ctx, $req, $sink, $option.get_option(), $option.filter.as_ref())
+    };
+}
+
 impl SFFSServer {
     fn getdir(&mut self) -> Option<sffs::String> {
         Some(env::current_dir().ok()?.into_os_string().into_string().ok()?.into())
@@ -57,37 +234,45 @@ impl SFFSServer {
     fn changedir(&mut self, req: &sffs::String) -> Option<sffs::Boolean> {
         Some(env::set_current_dir(req.get_value()).is_ok().into())
     }
-    fn filecount(&mut self, req: &sffs::ListOption) -> Option<sffs::Int64> {
-        let filter = match MetaDataFilter::new(req.get_option()) {
-            Some(filter) => filter,
-            None => return Some(0.into()),
-        };
-
-        let dots = ([".", ".."].iter())
-            .filter_map(|ename| File::open(ename).ok())
-            .filter_map(|f| f.metadata().ok());
-        let files = (fs::read_dir(".").ok()?)
-            .filter_map(|e| e.ok())
-            .filter_map(|e| e.metadata().ok());
-
-        let count = dots.chain(files).filter(|meta| filter.check(&meta)).count();
-
-        Some((count as i64).into())
+    fn makedir(&mut self, req: &sffs::String) -> Option<sffs::Boolean> {
+        Some(fs::create_dir_all(req.get_value()).is_ok().into())
     }
-    fn openlist(&mut self, req: &sffs::ListRequest) -> Option<sffs::Boolean> {
-        let filter = match MetaDataFilter::new(req.get_option().get_option()) {
-            Some(filter) => filter,
-            None => return Some(false.into()),
+    fn filecount(&mut self, req: &sffs::CountRequest, filter: &MetaDataFilter) -> Option<sffs::FileCount> {
+        let dir = match req.get_dir() {
+            "" => ".",
+            dir => dir,
         };
+        let entries = read_entries(Path::new(dir), filter)?;
 
+        let mut reply = sffs::FileCount::new();
+        reply.set_count(entries.len() as i64);
+        if req.get_stats() {
+            for entry in entries.iter() {
+                if entry.get_isdir() {
+                    add_stats(reply.mut_dirs(), entry);
+                } else {
+                    add_stats(reply.mut_files(), entry);
+                }
+            }
+        }
+        Some(reply)
+    }
+    fn stat(&mut self, req: &sffs::String) -> Option<sffs::DirEntry> {
+        match fs::metadata(req.get_value()) {
+            Ok(meta) => (req.get_value().to_owned(), meta).try_into().ok(),
+            Err(_) => Some(sffs::DirEntry::new()),
+        }
+    }
+    fn openlist(&mut self, req: &sffs::ListRequest, filter: &MetaDataFilter) -> Option<sffs::Boolean> {
         let mut guard = self.0.opendir.lock().ok()?;
 
         let res = if (*guard).is_some() {
             false
         } else {
-            *guard = fs::read_dir(req.get_dir())
-                .ok()
-                .map(|d| (d, req.get_dir().into(), NextEntry::Dot, filter));
+            *guard = read_entries(Path::new(req.get_dir()), filter).map(|mut entries| {
+                sort_entries(&mut entries, req.get_sort());
+                entries.into_iter()
+            });
             (*guard).is_some()
         };
         drop(guard); // release lock
@@ -97,49 +282,54 @@ impl SFFSServer {
     fn nextlist(&mut self) -> Option<sffs::DirEntry> {
         let mut guard = self.0.opendir.lock().ok()?;
 
-        let (ref mut dir, ref path, ref mut next, ref filter) = guard.as_mut()?;
-
-        let mut getnext = || -> Option<sffs::DirEntry> {
-            // There will definitely be one entry, even may be empty.
-            Some(loop {
-                match *next {
-                    // next is still file
-                    NextEntry::File => match dir.next() {
-                        Some(entry) => {
-                            let entry = entry.ok()?;
-                            let meta = entry.metadata().ok()?;
-                            if filter.check(&meta) {
-                                break sffs::DirEntry::try_from(entry).ok()?;
-                            }
-                        }
-                        None => break sffs::DirEntry::default(),
-                    },
-                    NextEntry::Dot => {
-                        *next = NextEntry::DotDot;
-                        let meta = File::open(path.join(".")).ok()?.metadata().ok()?;
-                        if filter.check(&meta) {
-                            break (".".to_owned(), meta).try_into().ok()?;
-                        }
-                    }
-                    NextEntry::DotDot => {
-                        *next = NextEntry::File;
-                        let meta = File::open(path.join("..")).ok()?.metadata().ok()?;
-                        if filter.check(&meta) {
-                            break ("..".to_owned(), meta).try_into().ok()?;
-                        }
-                    }
-                }
-            })
+        let entries = guard.as_mut()?;
+        // an empty entry marks the end
+        Some(entries.next().unwrap_or_default())
+    }
+    fn closelist(&mut self) -> Option<sffs::Boolean> {
+        let mut guard = self.0.opendir.lock().ok()?;
+        Some((*guard).take().is_some().into())
+    }
+    // The cursor is the last entry of the previous page, so a page starts
+    // after it in sort order even if entries were added or removed since.
+    fn listpage(&mut self, req: &sffs::ListRequest, filter: &MetaDataFilter) -> Option<sffs::DirPage> {
+        let sort = page_sort(req.get_sort());
+        let mut entries = read_entries(Path::new(req.get_dir()), filter)?;
+        sort_entries(&mut entries, &sort);
+
+        page_entries(entries, &sort, req.get_cursor(), req.get_limit())
+    }
+    fn openfind(&mut self, req: &sffs::FindRequest, filter: MetaDataFilter) -> Option<sffs::Boolean> {
+        let mut guard = self.0.openfind.lock().ok()?;
+
+        let res = if (*guard).is_some() {
+            false
+        } else {
+            let maxdepth = req.get_maxdepth().max(0) as usize;
+            *guard = Walk::new(req.get_dir(), maxdepth, req.get_follow())
+                .ok()
+                .map(|walk| (walk, filter));
+            (*guard).is_some()
         };
-        loop {
-            let entry = getnext();
-            if entry.is_some() {
-                return entry;
+        Some(res.into())
+    }
+    fn nextfind(&mut self) -> Option<sffs::DirEntry> {
+        let mut guard = self.0.openfind.lock().ok()?;
+
+        let (walk, filter) = guard.as_mut()?;
+        for (path, meta) in walk {
+            let name = path.file_name().map(|name| name.to_string_lossy()).unwrap_or_default();
+            if filter.check(&name, &meta) {
+                if let Ok(entry) = (path.to_string_lossy().into_owned(), meta).try_into() {
+                    return Some(entry);
+                }
             }
         }
+        // an empty entry marks the end
+        Some(sffs::DirEntry::default())
     }
-    fn closelist(&mut self) -> Option<sffs::Boolean> {
-        let mut guard = self.0.opendir.lock().ok()?;
+    fn closefind(&mut self) -> Option<sffs::Boolean> {
+        let mut guard = self.0.openfind.lock().ok()?;
         Some((*guard).take().is_some().into())
     }
     fn openfiletoread(&mut self, req: &sffs::String) -> Option<sffs::Boolean> {
@@ -205,6 +395,74 @@ impl SFFSServer {
         let mut guard = self.0.openfile.lock().ok()?;
         Some((*guard).take().is_some().into())
     }
+    fn listxattr(&mut self, req: &sffs::String) -> Option<sffs::XattrList> {
+        let names = (xattr::list(req.get_value()).ok()?)
+            .filter_map(|name| name.into_string().ok())
+            .collect::<Vec<_>>();
+        Some(names.into())
+    }
+    fn getxattr(&mut self, req: &sffs::Xattr) -> Option<sffs::XattrValue> {
+        Some(xattr::get(req.get_path(), req.get_name()).ok()?.into())
+    }
+    fn setxattr(&mut self, req: &sffs::Xattr) -> Option<sffs::Boolean> {
+        Some(xattr::set(req.get_path(), req.get_name(), req.get_value()).is_ok().into())
+    }
+    fn removexattr(&mut self, req: &sffs::Xattr) -> Option<sffs::Boolean> {
+        Some(xattr::remove(req.get_path(), req.get_name()).is_ok().into())
+    }
+    fn link(&mut self, req: &sffs::Link) -> Option<sffs::LinkResult> {
+        let res = fs::hard_link(req.get_source(), req.get_target()).and_then(|_| fs::metadata(req.get_target()));
+        Some(res.ok().map(|meta| meta.nlink()).into())
+    }
+    fn statfs(&mut self, req: &sffs::String) -> Option<sffs::FsStat> {
+        Some(statvfs::statvfs(req.get_value()).ok()?.into())
+    }
+    fn diskusage(&mut self, req: &sffs::DiskUsageRequest, filter: &MetaDataFilter) -> Option<sffs::DiskUsage> {
+        let mut total = sffs::DiskUsage::new();
+        total.set_name(req.get_dir().to_owned());
+
+        // shared by the children, a link in one is not counted again in another
+        let mut seen = HashSet::new();
+        for entry in (fs::read_dir(req.get_dir()).ok()?).filter_map(|e| e.ok()) {
+            let meta = match entry.metadata() {
+                Ok(meta) => meta,
+                Err(_) => continue,
+            };
+            let mut child = sffs::DiskUsage::new();
+            child.set_name(entry.file_name().to_string_lossy().into_owned());
+            accumulate(&entry.path(), &meta, filter, &mut seen, &mut child);
+
+            total.bytes += child.bytes;
+            total.used += child.used;
+            total.files += child.files;
+            total.dirs += child.dirs;
+            if req.get_perchild() {
+                total.mut_children().push(child);
+            }
+        }
+
+        Some(total)
+    }
+    fn checksum(&mut self, req: &sffs::ChecksumRequest) -> Option<sffs::Checksum> {
+        let mut reply = sffs::Checksum::new();
+
+        let mut file = match File::open(req.get_path()) {
+            Ok(file) => file,
+            Err(_) => return Some(reply), // not found
+        };
+        let range = if req.has_range() {
+            let range = req.get_range();
+            Some((range.get_start() as u64, range.get_count() as u64))
+        } else {
+            None
+        };
+        let (digest, bytes) = checksum::digest_file(&mut file, req.get_algorithm(), range).ok()?;
+
+        reply.set_found(true);
+        reply.set_digest(digest);
+        reply.set_bytes(bytes as i64);
+        Some(reply)
+    }
 }
 
 impl Sffs for SFFSServer {
@@ -217,12 +475,22 @@ impl Sffs for SFFSServer {
         reply!(ctx, req, to_future!(sink, self.changedir(&req)));
     }
     #[inline]
-    fn filecount(&mut self, ctx: RpcContext, req: sffs::ListOption, sink: UnarySink<sffs::Int64>) {
-        reply!(ctx, req, to_future!(sink, self.filecount(&req)));
+    fn makedir(&mut self, ctx: RpcContext, req: sffs::String, sink: UnarySink<sffs::Boolean>) {
+        reply!(ctx, req, to_future!(sink, self.makedir(&req)));
+    }
+    #[inline]
+    fn filecount(&mut self, ctx: RpcContext, req: sffs::CountRequest, sink: UnarySink<sffs::FileCount>) {
+        let filter = parse_filter!(ctx, req, sink, req.get_option(), req.filter.as_ref());
+        reply!(ctx, req, to_future!(sink, self.filecount(&req, &filter)));
+    }
+    #[inline]
+    fn stat(&mut self, ctx: RpcContext, req: sffs::String, sink: UnarySink<sffs::DirEntry>) {
+        reply!(ctx, req, to_future!(sink, self.stat(&req)));
     }
     #[inline]
     fn openlist(&mut self, ctx: RpcContext, req: sffs::ListRequest, sink: UnarySink<sffs::Boolean>) {
-        reply!(ctx, req, to_future!(sink, self.openlist(&req)));
+        let filter = parse_filter!(ctx, req, sink, req.get_option());
+        reply!(ctx, req, to_future!(sink, self.openlist(&req, &filter)));
     }
     #[inline]
     fn nextlist(&mut self, ctx: RpcContext, req: sffs::Void, sink: UnarySink<sffs::DirEntry>) {
@@ -233,6 +501,24 @@ impl Sffs for SFFSServer {
         reply!(ctx, req, to_future!(sink, self.closelist()));
     }
     #[inline]
+    fn listpage(&mut self, ctx: RpcContext, req: sffs::ListRequest, sink: UnarySink<sffs::DirPage>) {
+        let filter = parse_filter!(ctx, req, sink, req.get_option());
+        reply!(ctx, req, to_future!(sink, self.listpage(&req, &filter)));
+    }
+    #[inline]
+    fn openfind(&mut self, ctx: RpcContext, req: sffs::FindRequest, sink: UnarySink<sffs::Boolean>) {
+        let filter = parse_filter!(ctx, req, sink, req.get_option());
+        reply!(ctx, req, to_future!(sink, self.openfind(&req, filter)));
+    }
+    #[inline]
+    fn nextfind(&mut self, ctx: RpcContext, req: sffs::Void, sink: UnarySink<sffs::DirEntry>) {
+        reply!(ctx, req, to_future!(sink, self.nextfind()));
+    }
+    #[inline]
+    fn closefind(&mut self, ctx: RpcContext, req: sffs::Void, sink: UnarySink<sffs::Boolean>) {
+        reply!(ctx, req, to_future!(sink, self.closefind()));
+    }
+    #[inline]
     fn openfiletoread(&mut self, ctx: RpcContext, req: sffs::String, sink: UnarySink<sffs::Boolean>) {
         reply!(ctx, req, to_future!(sink, self.openfiletoread(&req)));
     }
@@ -256,4 +542,152 @@ impl Sffs for SFFSServer {
     fn closefile(&mut self, ctx: RpcContext, req: sffs::Void, sink: UnarySink<sffs::Boolean>) {
         reply!(ctx, req, to_future!(sink, self.closefile()));
     }
+    #[inline]
+    fn listxattr(&mut self, ctx: RpcContext, req: sffs::String, sink: UnarySink<sffs::XattrList>) {
+        reply!(ctx, req, to_future!(sink, self.listxattr(&req)));
+    }
+    #[inline]
+    fn getxattr(&mut self, ctx: RpcContext, req: sffs::Xattr, sink: UnarySink<sffs::XattrValue>) {
+        reply!(ctx, req, to_future!(sink, self.getxattr(&req)));
+    }
+    #[inline]
+    fn setxattr(&mut self, ctx: RpcContext, req: sffs::Xattr, sink: UnarySink<sffs::Boolean>) {
+        reply!(ctx, req, to_future!(sink, self.setxattr(&req)));
+    }
+    #[inline]
+    fn removexattr(&mut self, ctx: RpcContext, req: sffs::Xattr, sink: UnarySink<sffs::Boolean>) {
+        reply!(ctx, req, to_future!(sink, self.removexattr(&req)));
+    }
+    #[inline]
+    fn link(&mut self, ctx: RpcContext, req: sffs::Link, sink: UnarySink<sffs::LinkResult>) {
+        reply!(ctx, req, to_future!(sink, self.link(&req)));
+    }
+    #[inline]
+    fn statfs(&mut self, ctx: RpcContext, req: sffs::String, sink: UnarySink<sffs::FsStat>) {
+        reply!(ctx, req, to_future!(sink, self.statfs(&req)));
+    }
+    #[inline]
+    fn diskusage(&mut self, ctx: RpcContext, req: sffs::DiskUsageRequest, sink: UnarySink<sffs::DiskUsage>) {
+        let filter = parse_filter!(ctx, req, sink, req.get_option());
+        reply!(ctx, req, to_future!(sink, self.diskusage(&req, &filter)));
+    }
+    #[inline]
+    fn checksum(&mut self, ctx: RpcContext, req: sffs::ChecksumRequest, sink: UnarySink<sffs::Checksum>) {
+        reply!(ctx, req, to_future!(sink, self.checksum(&req)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sffs::SortKey::*;
+
+    fn entry(name: &str, isdir: bool, size: i64) -> sffs::DirEntry {
+        let mut entry = sffs::DirEntry::new();
+        entry.set_name(name.to_owned());
+        entry.set_isdir(isdir);
+        entry.set_size(size);
+        entry
+    }
+
+    fn sort(key: sffs::SortKey, reverse: bool, dirsfirst: bool) -> sffs::SortOption {
+        let mut sort = sffs::SortOption::new();
+        sort.set_key(key);
+        sort.set_reverse(reverse);
+        sort.set_dirsfirst(dirsfirst);
+        sort
+    }
+
+    fn sorted(mut entries: Vec<sffs::DirEntry>, sort: &sffs::SortOption) -> Vec<String> {
+        sort_entries(&mut entries, sort);
+        entries.into_iter().map(|mut e| e.take_name()).collect()
+    }
+
+    fn names(page: &sffs::DirPage) -> Vec<&str> {
+        page.get_entries().iter().map(|e| e.get_name()).collect()
+    }
+
+    fn abc() -> Vec<sffs::DirEntry> {
+        vec![entry("a", false, 3), entry("b", true, 1), entry("c", false, 1)]
+    }
+
+    #[test]
+    fn size_ties_are_broken_by_name() {
+        let entries = vec![entry("c", false, 1), entry("a", false, 3), entry("b", false, 1)];
+        assert_eq!(sorted(entries.clone(), &sort(SIZE, false, false)), ["b", "c", "a"]);
+        assert_eq!(sorted(entries, &sort(SIZE, true, false)), ["a", "c", "b"]);
+    }
+
+    #[test]
+    fn dirsfirst_is_not_reversed() {
+        let entries = vec![entry("a", false, 0), entry("d", true, 0), entry("b", true, 0), entry("c", false, 0)];
+        assert_eq!(sorted(entries.clone(), &sort(NAME, false, true)), ["b", "d", "a", "c"]);
+        assert_eq!(sorted(entries, &sort(NAME, true, true)), ["d", "b", "c", "a"]);
+    }
+
+    #[test]
+    fn none_keeps_order_and_pages_by_name() {
+        let entries = vec![entry("b", false, 0), entry("a", false, 0)];
+        assert_eq!(sorted(entries, &sort(NONE, false, false)), ["b", "a"]);
+        assert_eq!(page_sort(&sort(NONE, true, true)), sort(NAME, true, true));
+        assert_eq!(page_sort(&sort(SIZE, false, false)), sort(SIZE, false, false));
+    }
+
+    #[test]
+    fn pages_of_one() {
+        let sort = sort(NAME, false, false);
+        let mut cursor = Vec::new();
+        let mut seen = Vec::new();
+        loop {
+            let mut page = page_entries(abc(), &sort, &cursor, 1).unwrap();
+            seen.extend(names(&page).into_iter().map(str::to_owned));
+            cursor = page.take_cursor();
+            if cursor.is_empty() {
+                break;
+            }
+            assert_eq!(page.get_entries().len(), 1);
+        }
+        assert_eq!(seen, ["a", "b", "c"]);
+    }
+
+    #[test]
+    fn no_limit_is_one_page() {
+        let page = page_entries(abc(), &sort(NAME, false, false), &[], 0).unwrap();
+        assert_eq!(names(&page), ["a", "b", "c"]);
+        assert!(page.get_cursor().is_empty());
+    }
+
+    #[test]
+    fn cursor_after_the_last_entry() {
+        let cursor = entry("z", false, 0).write_to_bytes().unwrap();
+        let page = page_entries(abc(), &sort(NAME, false, false), &cursor, 2).unwrap();
+        assert!(names(&page).is_empty());
+        assert!(page.get_cursor().is_empty());
+    }
+
+    #[test]
+    fn cursor_of_a_deleted_entry() {
+        // "b" ended the previous page and is gone now
+        let entries = vec![entry("a", false, 3), entry("c", false, 1), entry("d", false, 1)];
+        let cursor = entry("b", true, 1).write_to_bytes().unwrap();
+        let page = page_entries(entries, &sort(NAME, false, false), &cursor, 1).unwrap();
+        assert_eq!(names(&page), ["c"]);
+        assert!(!page.get_cursor().is_empty());
+    }
+
+    #[test]
+    fn cursor_with_reverse_and_dirsfirst() {
+        let sort = sort(SIZE, true, true);
+        let mut entries = abc();
+        sort_entries(&mut entries, &sort);
+        let cursor = entries[1].write_to_bytes().unwrap();
+        let page = page_entries(entries, &sort, &cursor, 5).unwrap();
+        // b is the only directory, then a (3) and c (1) by decreasing size
+        assert_eq!(names(&page), ["c"]);
+    }
+
+    #[test]
+    fn malformed_cursor() {
+        assert!(page_entries(abc(), &sort(NAME, false, false), b"\xff", 1).is_none());
+    }
 }
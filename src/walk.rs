@@ -0,0 +1,74 @@
+use std::collections::HashSet;
+use std::fs::{self, Metadata, ReadDir};
+use std::io;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+// Depth-first walk below a directory, yielding paths relative to it. The
+// directory itself is not yielded, its entries are at depth 1.
+pub struct Walk {
+    root: PathBuf,
+    stack: Vec<(ReadDir, PathBuf, usize)>, // open directory, its relative path and depth
+    maxdepth: usize,                       // 0 means unlimited
+    follow: bool,                          // follow symlinks
+    visited: HashSet<(u64, u64)>,          // (dev, ino) of directories, against symlink loops
+}
+
+impl Walk {
+    pub fn new<P: AsRef<Path>>(root: P, maxdepth: usize, follow: bool) -> io::Result<Self> {
+        let root = root.as_ref().to_path_buf();
+        let meta = fs::metadata(&root)?;
+        let dir = fs::read_dir(&root)?;
+
+        let mut visited = HashSet::new();
+        visited.insert((meta.dev(), meta.ino()));
+        Ok(Self {
+            root,
+            stack: vec![(dir, PathBuf::new(), 0)],
+            maxdepth,
+            follow,
+            visited,
+        })
+    }
+}
+
+impl Iterator for Walk {
+    type Item = (PathBuf, Metadata);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (entry, parent, depth) = {
+                let (dir, parent, depth) = self.stack.last_mut()?;
+                match dir.next() {
+                    Some(Ok(entry)) => (entry, parent.clone(), *depth + 1),
+                    Some(Err(_)) => continue, // unreadable entry, skip it
+                    None => {
+                        self.stack.pop();
+                        continue;
+                    }
+                }
+            };
+
+            let path = parent.join(entry.file_name());
+            let meta = if self.follow {
+                // a dangling symlink is reported as the link itself
+                fs::metadata(entry.path()).or_else(|_| entry.metadata())
+            } else {
+                entry.metadata()
+            };
+            let meta = match meta {
+                Ok(meta) => meta,
+                Err(_) => continue,
+            };
+
+            let descend = meta.is_dir() && (self.maxdepth == 0 || depth < self.maxdepth);
+            if descend && self.visited.insert((meta.dev(), meta.ino())) {
+                if let Ok(dir) = fs::read_dir(self.root.join(&path)) {
+                    self.stack.push((dir, path.clone(), depth));
+                }
+            }
+
+            return Some((path, meta));
+        }
+    }
+}